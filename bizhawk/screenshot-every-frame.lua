@@ -1,9 +1,171 @@
-frame_num = 0
-fn_format = "Screenshots/%04d.png"
+-- settings
+output_dir = "Screenshots"
+-- zero padding for the frame number in file names. 6 digits lasts for over 4 hours at 60fps
+digits = 6
+-- only save every nth frame (inputs are still logged for every frame)
+every_nth = 1
+-- stop by itself after this many frames. 0 keeps going until the script is stopped
+stop_after = 0
+-- pressing this key starts and stops capturing. set to nil to always capture
+hotkey = "F9"
+-- whether to capture straight away, or wait for the hotkey
+start_capturing = true
+-- also log link's position from ram to positions.csv. only makes sense for alttp
+log_alttp_positions = true
+
+fn_format = output_dir .. "/%0" .. digits .. "d.png"
+
+-- frame numbers in file names and both logs are emu.framecount(), the same count as
+-- capture_start_frame in manifest.json
+
+-- os.execute mkdir is the one way to make a directory that works on both windows and linux.
+-- it fails harmlessly if the directory is already there
+os.execute('mkdir "' .. output_dir .. '"')
+
+-- like io.open, but stops the script with a clear message instead of handing back nil
+function open_or_stop(path, mode)
+    local f, err = io.open(path, mode)
+    if f == nil then
+        error(string.format("Couldn't open %s (%s). does %s exist?", path, err, output_dir))
+    end
+    return f
+end
+
+-- one line per frame: the frame number and the buttons held, in bizhawk's input log order
+-- (UDLRsSYBXAlr), with a . for each button that isn't held
+input_log = open_or_stop(output_dir .. "/inputs.log", "w")
+buttons = {"Up", "Down", "Left", "Right", "Select", "Start", "Y", "B", "X", "A", "L", "R"}
+mnemonics = {"U", "D", "L", "R", "s", "S", "Y", "B", "X", "A", "l", "r"}
+
+function input_mnemonic()
+    local held = joypad.get(1)
+    local out = ""
+    for i, button in ipairs(buttons) do
+        if held[button] then
+            out = out .. mnemonics[i]
+        else
+            out = out .. "."
+        end
+    end
+    return out
+end
+
+-- link's position every frame, read from alttp's wram. screen_x/screen_y are relative to the top
+-- left of the screen; that's his coordinate, not quite the top left of his sprite, and the rust
+-- side measures the difference against find_link. the rest are the raw ram values
+positions_log = nil
+if log_alttp_positions then
+    positions_log = open_or_stop(output_dir .. "/positions.csv", "w")
+    positions_log:write("frame,screen_x,screen_y,direction,indoors,room\n")
+end
+
+function log_alttp_position()
+    local y = memory.read_u16_le(0x20, "WRAM")
+    local x = memory.read_u16_le(0x22, "WRAM")
+    -- 0 up, 2 down, 4 left, 6 right
+    local direction = memory.read_u8(0x2F, "WRAM")
+    local indoors = memory.read_u8(0x1B, "WRAM")
+    -- the dungeon room when indoors, otherwise the overworld area
+    local room
+    if indoors ~= 0 then
+        room = memory.read_u16_le(0xA0, "WRAM")
+    else
+        room = memory.read_u8(0x8A, "WRAM")
+    end
+    -- bg2 scroll, i.e. where the camera is
+    local scroll_x = memory.read_u16_le(0xE2, "WRAM")
+    local scroll_y = memory.read_u16_le(0xE8, "WRAM")
+    positions_log:write(string.format("%d,%d,%d,%d,%d,%d\n",
+        frame_num, x - scroll_x, y - scroll_y, direction, indoors, room))
+    positions_log:flush()
+end
+
+-- frames per second by system id, for ntsc. pal systems are handled below
+ntsc_fps = {SNES = 60.0988, NES = 60.0988, GB = 59.7275, GBC = 59.7275, GBA = 59.7275, GEN = 59.9228}
+
+function frame_rate()
+    local system = emu.getsystemid()
+    if emu.getdisplaytype() == "PAL" then
+        return 50.007
+    end
+    return ntsc_fps[system] or 60
+end
+
+function json_string(s)
+    return '"' .. string.gsub(s, '[%c"\\]', function(c)
+        return string.format("\\u%04x", string.byte(c))
+    end) .. '"'
+end
+
+-- everything the rust side needs to know about this capture, read from manifest.json
+function write_manifest(capture_start_frame)
+    local f = open_or_stop(output_dir .. "/manifest.json", "w")
+    f:write("{\n")
+    f:write(string.format('  "rom_name": %s,\n', json_string(gameinfo.getromname())))
+    f:write(string.format('  "system": %s,\n', json_string(emu.getsystemid())))
+    f:write(string.format('  "fps": %s,\n', frame_rate()))
+    f:write(string.format('  "every_nth": %d,\n', every_nth))
+    f:write(string.format('  "capture_start_frame": %d,\n', capture_start_frame))
+    f:write(string.format('  "digits": %d,\n', digits))
+    f:write('  "extension": "png",\n')
+    if positions_log ~= nil then
+        f:write('  "positions_log": "positions.csv",\n')
+    end
+    f:write('  "input_log": "inputs.log"\n')
+    f:write("}\n")
+    f:close()
+end
+
+capturing = false
+hotkey_was_down = false
+frame_num = emu.framecount()
+capture_start_frame = frame_num
+captured = 0
+
+function set_capturing(on)
+    if on and not capturing then
+        print(string.format("Capturing to %s", output_dir))
+        capture_start_frame = emu.framecount()
+        -- stop_after counts each capture on its own
+        captured = 0
+        write_manifest(capture_start_frame)
+    elseif capturing and not on then
+        print(string.format("Stopped capturing after %d frames", captured))
+    end
+    capturing = on
+end
+
+set_capturing(start_capturing or hotkey == nil)
 while true do
-    frame_num = frame_num + 1
-    filename = string.format(fn_format, frame_num)
-    print(string.format("Saving file to %s", filename))
-    client.screenshot(filename)
+    if hotkey ~= nil then
+        local hotkey_down = input.get()[hotkey] == true
+        if hotkey_down and not hotkey_was_down then
+            set_capturing(not capturing)
+        end
+        hotkey_was_down = hotkey_down
+    end
+
+    frame_num = emu.framecount()
+    if capturing then
+        if (frame_num - capture_start_frame) % every_nth == 0 then
+            filename = string.format(fn_format, frame_num)
+            print(string.format("Saving file to %s", filename))
+            client.screenshot(filename)
+        end
+        input_log:write(string.format("%d %s\n", frame_num, input_mnemonic()))
+        input_log:flush()
+        if positions_log ~= nil then
+            log_alttp_position()
+        end
+        captured = captured + 1
+        if stop_after > 0 and captured >= stop_after then
+            set_capturing(false)
+            break
+        end
+    end
 	emu.frameadvance();
 end
+input_log:close()
+if positions_log ~= nil then
+    positions_log:close()
+end
@@ -0,0 +1,92 @@
+use crate::Cropper;
+use image::{DynamicImage, GenericImageView, Pixel};
+
+/// how `b` lines up with `a`: frame `i` of `a` matches frame `i + offset` of `b`
+#[derive(Debug, Clone, Copy)]
+pub struct Alignment {
+    pub offset: i64,
+    /// mean per-channel difference over the window, 0 (identical) to 1
+    pub score: f64,
+}
+
+impl Alignment {
+    /// how many frames to skip in (`a`, `b`) so they start in sync
+    pub fn skips(&self) -> (usize, usize) {
+        if self.offset >= 0 {
+            (0, self.offset as usize)
+        } else {
+            ((-self.offset) as usize, 0)
+        }
+    }
+}
+
+/// mean absolute per-channel difference between two frames, scaled to 0-1.
+/// frames of different sizes are considered completely different
+pub fn mean_abs_difference(a: &DynamicImage, b: &DynamicImage) -> f64 {
+    if a.dimensions() != b.dimensions() {
+        return 1.0;
+    }
+    let count = a.width() as u64 * a.height() as u64 * 3;
+    if count == 0 {
+        return 0.0;
+    }
+    let total: u64 = a
+        .pixels()
+        .zip(b.pixels())
+        .map(|((_, _, pa), (_, _, pb))| {
+            pa.to_rgb()
+                .0
+                .iter()
+                .zip(pb.to_rgb().0.iter())
+                .map(|(ca, cb)| ca.abs_diff(*cb) as u64)
+                .sum::<u64>()
+        })
+        .sum();
+    total as f64 / (count as f64 * 255.0)
+}
+
+/// tries every offset in `-max_offset..=max_offset` and returns the one where the first `window`
+/// overlapping frames differ the least. if `crop` is given only that region of each frame is
+/// compared, which helps when e.g. the hud differs between the two runs.
+///
+/// offsets that don't leave `window` overlapping frames are skipped, so returns `None` if the
+/// sequences are too short for any offset
+pub fn find_best_offset(
+    a: &[DynamicImage],
+    b: &[DynamicImage],
+    max_offset: usize,
+    window: usize,
+    crop: Option<&Cropper>,
+) -> Option<Alignment> {
+    if window == 0 {
+        return None;
+    }
+    let prepare = |frames: &[DynamicImage]| -> Vec<DynamicImage> {
+        frames
+            .iter()
+            .map(|f| match crop {
+                Some(c) => c.crop_around_middle(f),
+                None => f.clone(),
+            })
+            .collect()
+    };
+    let a = prepare(a);
+    let b = prepare(b);
+
+    let mut best: Option<Alignment> = None;
+    let max_offset = max_offset as i64;
+    for offset in -max_offset..=max_offset {
+        let (skip_a, skip_b) = Alignment { offset, score: 0.0 }.skips();
+        if skip_a + window > a.len() || skip_b + window > b.len() {
+            continue;
+        }
+        let score = (0..window)
+            .map(|i| mean_abs_difference(&a[skip_a + i], &b[skip_b + i]))
+            .sum::<f64>()
+            / window as f64;
+        if best.is_none_or(|b| score < b.score) {
+            best = Some(Alignment { offset, score });
+        }
+    }
+    best
+}
@@ -0,0 +1,271 @@
+use crate::env_var_opt;
+use ab_glyph::FontRef;
+use image::{DynamicImage, Rgba, RgbaImage};
+use image_misc::fonts::bundled_font;
+use imageproc::drawing::{
+    draw_filled_rect_mut, draw_hollow_rect_mut, draw_line_segment_mut, draw_text_mut, text_size,
+};
+use imageproc::rect::Rect;
+use std::fs::read_to_string;
+
+const OUTLINE: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+/// an opaque colour from `key`, written like `255,255,0`
+pub fn colour_from_env(key: &str) -> anyhow::Result<Option<Rgba<u8>>> {
+    let Some(c) = env_var_opt(key) else {
+        return Ok(None);
+    };
+    let channels = c
+        .split(',')
+        .map(|c| c.trim().parse())
+        .collect::<Result<Vec<u8>, _>>()?;
+    match channels[..] {
+        [r, g, b] => Ok(Some(Rgba([r, g, b, 255]))),
+        _ => Err(anyhow::anyhow!("{key} should be r,g,b")),
+    }
+}
+
+fn white() -> [u8; 3] {
+    [255, 255, 255]
+}
+
+fn default_size() -> f32 {
+    16.0
+}
+
+fn default_thickness() -> u32 {
+    2
+}
+
+/// something to draw on a frame. coordinates are in output pixels, i.e. after any resizing
+#[derive(serde::Deserialize, Debug, Clone)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Shape {
+    Caption {
+        text: String,
+        x: i32,
+        y: i32,
+        #[serde(default = "default_size")]
+        size: f32,
+        #[serde(default = "white")]
+        colour: [u8; 3],
+    },
+    /// counts output frames from the start of the annotation's range
+    FrameCounter {
+        x: i32,
+        y: i32,
+        #[serde(default = "default_size")]
+        size: f32,
+        #[serde(default = "white")]
+        colour: [u8; 3],
+    },
+    /// text on a filled box, like the button labels of an input display
+    Label {
+        text: String,
+        x: i32,
+        y: i32,
+        #[serde(default = "default_size")]
+        size: f32,
+        #[serde(default = "white")]
+        colour: [u8; 3],
+        #[serde(default)]
+        background: [u8; 3],
+    },
+    Arrow {
+        from: (i32, i32),
+        to: (i32, i32),
+        #[serde(default = "white")]
+        colour: [u8; 3],
+        #[serde(default = "default_thickness")]
+        thickness: u32,
+    },
+    Box {
+        x: i32,
+        y: i32,
+        width: u32,
+        height: u32,
+        #[serde(default = "white")]
+        colour: [u8; 3],
+        #[serde(default = "default_thickness")]
+        thickness: u32,
+    },
+}
+
+/// a shape and the output frames it's shown on. `end` is inclusive, and leaving it out shows the
+/// shape until the end of the gif
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct Annotation {
+    #[serde(default)]
+    pub start: usize,
+    #[serde(default)]
+    pub end: Option<usize>,
+    #[serde(flatten)]
+    pub shape: Shape,
+}
+
+impl Annotation {
+    fn shown_on(&self, frame: usize) -> bool {
+        frame >= self.start && self.end.is_none_or(|end| frame <= end)
+    }
+}
+
+pub struct Annotator<'a> {
+    annotations: Vec<Annotation>,
+    font: FontRef<'a>,
+}
+
+impl Annotator<'static> {
+    /// reads the json list of annotations at `ANNOTATIONS`, if it's set
+    pub fn from_env() -> anyhow::Result<Option<Self>> {
+        let Some(path) = env_var_opt("ANNOTATIONS") else {
+            return Ok(None);
+        };
+        let annotations: Vec<Annotation> = serde_json::from_str(&read_to_string(&path)?)
+            .map_err(|e| anyhow::anyhow!("Error parsing annotations in {path}: {e}"))?;
+        let font = bundled_font("arial_bold")?;
+        Ok(Some(Self { annotations, font }))
+    }
+}
+
+impl<'a> Annotator<'a> {
+    /// draws every annotation that's shown on output frame `frame`
+    pub fn annotate(&self, frame: usize, image: DynamicImage) -> DynamicImage {
+        let mut image = image.into_rgba8();
+        for a in self.annotations.iter().filter(|a| a.shown_on(frame)) {
+            draw_shape(&mut image, &a.shape, frame - a.start, &self.font);
+        }
+        DynamicImage::ImageRgba8(image)
+    }
+}
+
+/// draws text with a 1px black outline around it, so it's readable on any background
+pub fn draw_outlined_text(
+    image: &mut RgbaImage,
+    colour: Rgba<u8>,
+    x: i32,
+    y: i32,
+    size: f32,
+    font: &FontRef,
+    text: &str,
+) {
+    for (dx, dy) in [
+        (-1, -1),
+        (0, -1),
+        (1, -1),
+        (-1, 0),
+        (1, 0),
+        (-1, 1),
+        (0, 1),
+        (1, 1),
+    ] {
+        draw_text_mut(image, OUTLINE, x + dx, y + dy, size, font, text);
+    }
+    draw_text_mut(image, colour, x, y, size, font, text);
+}
+
+/// a line `thickness` pixels wide with a 1px black outline
+fn draw_thick_line(
+    image: &mut RgbaImage,
+    from: (f32, f32),
+    to: (f32, f32),
+    colour: Rgba<u8>,
+    thickness: u32,
+) {
+    let half = thickness as i32 / 2;
+    for (c, spread) in [(OUTLINE, half + 1), (colour, half)] {
+        for dx in -spread..=spread {
+            for dy in -spread..=spread {
+                let (dx, dy) = (dx as f32, dy as f32);
+                draw_line_segment_mut(image, (from.0 + dx, from.1 + dy), (to.0 + dx, to.1 + dy), c);
+            }
+        }
+    }
+}
+
+fn rgba(c: [u8; 3]) -> Rgba<u8> {
+    Rgba([c[0], c[1], c[2], 255])
+}
+
+fn draw_shape(image: &mut RgbaImage, shape: &Shape, frames_in: usize, font: &FontRef) {
+    match shape {
+        Shape::Caption {
+            text,
+            x,
+            y,
+            size,
+            colour,
+        } => draw_outlined_text(image, rgba(*colour), *x, *y, *size, font, text),
+        Shape::FrameCounter { x, y, size, colour } => draw_outlined_text(
+            image,
+            rgba(*colour),
+            *x,
+            *y,
+            *size,
+            font,
+            &frames_in.to_string(),
+        ),
+        Shape::Label {
+            text,
+            x,
+            y,
+            size,
+            colour,
+            background,
+        } => {
+            let (w, h) = text_size(*size, font, text);
+            let padding = 2;
+            draw_filled_rect_mut(
+                image,
+                Rect::at(x - padding, y - padding)
+                    .of_size(w + 2 * padding as u32, h + 2 * padding as u32),
+                rgba(*background),
+            );
+            draw_text_mut(image, rgba(*colour), *x, *y, *size, font, text);
+        }
+        Shape::Arrow {
+            from,
+            to,
+            colour,
+            thickness,
+        } => {
+            let from = (from.0 as f32, from.1 as f32);
+            let to = (to.0 as f32, to.1 as f32);
+            draw_thick_line(image, from, to, rgba(*colour), *thickness);
+            // two short lines at 30 degrees either side of the shaft make the head
+            let angle = (from.1 - to.1).atan2(from.0 - to.0);
+            let head_length = 4.0 + 2.0 * *thickness as f32;
+            for side in [-1.0f32, 1.0] {
+                let a = angle + side * std::f32::consts::FRAC_PI_6;
+                let end = (to.0 + head_length * a.cos(), to.1 + head_length * a.sin());
+                draw_thick_line(image, to, end, rgba(*colour), *thickness);
+            }
+        }
+        Shape::Box {
+            x,
+            y,
+            width,
+            height,
+            colour,
+            thickness,
+        } => {
+            // outline on the outside and inside, then the box itself in between
+            let t = *thickness as i32;
+            for inset in -1..=t {
+                let c = if inset == -1 || inset == t {
+                    OUTLINE
+                } else {
+                    rgba(*colour)
+                };
+                let w = *width as i32 - 2 * inset;
+                let h = *height as i32 - 2 * inset;
+                if w > 0 && h > 0 {
+                    draw_hollow_rect_mut(
+                        image,
+                        Rect::at(x + inset, y + inset).of_size(w as u32, h as u32),
+                        c,
+                    );
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,86 @@
+//! snes pixels aren't square. these stretch frames horizontally to what they'd look like on a tv,
+//! without blurring them: each output column is a weighted average of the input columns it
+//! covers, so after an integer upscale only the columns that straddle a pixel boundary blend
+
+use anyhow::anyhow;
+use image::{DynamicImage, Rgba, RgbaImage};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelAspect {
+    /// the snes' actual pixel aspect ratio
+    Par8x7,
+    /// whatever makes a full 256x224 frame exactly 4:3, i.e. 7:6
+    Tv4x3,
+}
+
+impl PixelAspect {
+    /// how much wider than tall each pixel is
+    pub fn ratio(&self) -> f64 {
+        match self {
+            PixelAspect::Par8x7 => 8.0 / 7.0,
+            PixelAspect::Tv4x3 => (4.0 / 3.0) / (256.0 / 224.0),
+        }
+    }
+}
+
+impl FromStr for PixelAspect {
+    type Err = anyhow::Error;
+
+    /// `8:7` or `4:3`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "8:7" => Ok(PixelAspect::Par8x7),
+            "4:3" => Ok(PixelAspect::Tv4x3),
+            other => Err(anyhow!("Unknown aspect ratio {other}")),
+        }
+    }
+}
+
+/// stretches `image` horizontally by the pixel aspect ratio. best used after an integer upscale,
+/// since the more output columns each source pixel covers, the fewer of them get blended
+pub fn correct_aspect(image: &DynamicImage, aspect: PixelAspect) -> DynamicImage {
+    let out_width = (image.width() as f64 * aspect.ratio()).round() as u32;
+    DynamicImage::ImageRgba8(resample_width(&image.to_rgba8(), out_width))
+}
+
+/// box-filter resample to `out_width`, leaving the height alone
+pub fn resample_width(image: &RgbaImage, out_width: u32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    if out_width == width || width == 0 {
+        return image.clone();
+    }
+    // how many input columns each output column covers
+    let step = width as f64 / out_width as f64;
+    let mut out = RgbaImage::new(out_width, height);
+    for out_x in 0..out_width {
+        let start = out_x as f64 * step;
+        let end = start + step;
+        // (input column, how much of it falls in this output column)
+        let mut weights = vec![];
+        let mut x = start.floor() as u32;
+        while (x as f64) < end && x < width {
+            let covered = (end.min(x as f64 + 1.0) - start.max(x as f64)).max(0.0);
+            if covered > 0.0 {
+                weights.push((x, covered));
+            }
+            x += 1;
+        }
+        let total: f64 = weights.iter().map(|(_, w)| w).sum();
+        for y in 0..height {
+            let mut channels = [0.0f64; 4];
+            for (x, w) in &weights {
+                let px = image.get_pixel(*x, y);
+                for (c, v) in channels.iter_mut().zip(px.0.iter()) {
+                    *c += *v as f64 * w;
+                }
+            }
+            out.put_pixel(
+                out_x,
+                y,
+                Rgba(channels.map(|c| (c / total).round().clamp(0.0, 255.0) as u8)),
+            );
+        }
+    }
+    out
+}
@@ -0,0 +1,552 @@
+//! one on-disk cache for everything the binaries download.
+//!
+//! bodies are stored by a hash of their contents under `objects/`, and `index.json` maps each
+//! key (normally the url) to its hash. a body is checked against its hash every time it's read,
+//! so a file that's been truncated or scribbled on is thrown away and fetched again rather than
+//! used. if there's a size limit the least recently used entries are dropped to stay under it.
+//!
+//! several processes can share a cache. every change to the index is made while holding
+//! `index.lock`, to the copy on disk rather than the one in memory, so nobody's entries get
+//! written over; and every file goes to a uniquely named temporary file first and is renamed
+//! into place
+
+use crate::fetch::Fetcher;
+use anyhow::anyhow;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// a lock older than this was left by a process that died holding it
+const STALE_LOCK: Duration = Duration::from_secs(30);
+const LOCK_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// checks a freshly downloaded body is what it should be before it's cached
+pub type Validate = fn(&[u8]) -> anyhow::Result<()>;
+
+pub fn validate_any(_: &[u8]) -> anyhow::Result<()> {
+    Ok(())
+}
+
+/// the body has to decode as an image
+pub fn validate_image(body: &[u8]) -> anyhow::Result<()> {
+    image::load_from_memory(body)?;
+    Ok(())
+}
+
+/// 64 bit FNV-1a, as 16 hex digits. not cryptographic, but plenty for spotting a damaged file
+pub fn content_hash(data: &[u8]) -> String {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for b in data {
+        hash ^= *b as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    format!("{hash:016x}")
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or_default()
+}
+
+/// writes next to `path` then renames. the temporary name is unique to this process and call,
+/// so two writers of the same file never trip over each other
+fn write_atomic(path: &Path, data: &[u8]) -> anyhow::Result<()> {
+    static COUNTER: AtomicUsize = AtomicUsize::new(0);
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    fs::write(&tmp, data)?;
+    fs::rename(&tmp, path)?;
+    Ok(())
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Entry {
+    pub hash: String,
+    pub size: u64,
+    /// unix seconds
+    pub fetched_at: u64,
+    /// unix seconds
+    pub last_used: u64,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+struct Index {
+    /// url -> entry
+    entries: BTreeMap<String, Entry>,
+}
+
+impl Index {
+    /// bytes on disk. bodies shared by several urls are only counted once
+    fn total_size(&self) -> u64 {
+        let mut seen = HashSet::new();
+        self.entries
+            .values()
+            .filter(|e| seen.insert(e.hash.as_str()))
+            .map(|e| e.size)
+            .sum()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct VerifyReport {
+    pub ok: usize,
+    /// urls whose body was missing or didn't match its hash. they've been removed
+    pub corrupt: Vec<String>,
+    /// object files nothing pointed at. they've been deleted
+    pub orphans: usize,
+}
+
+/// `index.lock`, held for as long as this is alive
+struct IndexLock(PathBuf);
+
+impl IndexLock {
+    fn acquire(dir: &Path) -> anyhow::Result<Self> {
+        let path = dir.join("index.lock");
+        let start = Instant::now();
+        loop {
+            match fs::OpenOptions::new()
+                .write(true)
+                .create_new(true)
+                .open(&path)
+            {
+                Ok(_) => return Ok(Self(path)),
+                Err(e) if e.kind() == ErrorKind::AlreadyExists => {
+                    let stale = fs::metadata(&path)
+                        .and_then(|m| m.modified())
+                        .ok()
+                        .and_then(|t| t.elapsed().ok())
+                        .is_some_and(|age| age > STALE_LOCK);
+                    if stale {
+                        let _ = fs::remove_file(&path);
+                    } else if start.elapsed() > LOCK_TIMEOUT {
+                        return Err(anyhow!("Timed out waiting for {}", path.display()));
+                    } else {
+                        std::thread::sleep(Duration::from_millis(5));
+                    }
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+}
+
+impl Drop for IndexLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.0);
+    }
+}
+
+struct State {
+    /// the index as of the last time we read or wrote it
+    index: Index,
+    /// key -> when we read it, waiting to be saved to the index
+    touched: HashMap<String, u64>,
+}
+
+pub struct AssetCache {
+    dir: PathBuf,
+    max_bytes: Option<u64>,
+    state: Mutex<State>,
+}
+
+impl AssetCache {
+    pub fn open<P: Into<PathBuf>>(dir: P, max_bytes: Option<u64>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(dir.join("objects"))?;
+        let index = Self::read_index(&dir)?;
+        Ok(Self {
+            dir,
+            max_bytes,
+            state: Mutex::new(State {
+                index,
+                touched: HashMap::new(),
+            }),
+        })
+    }
+
+    /// `ASSET_CACHE_DIR` (default `./asset_cache`) and `ASSET_CACHE_MAX_MB` (default no limit)
+    pub fn from_env() -> anyhow::Result<Self> {
+        let var = |k: &str| std::env::var(k).ok().filter(|v| !v.is_empty());
+        let dir = var("ASSET_CACHE_DIR").unwrap_or_else(|| "./asset_cache".to_string());
+        let max_bytes = var("ASSET_CACHE_MAX_MB")
+            .map(|v| v.parse::<u64>())
+            .transpose()?
+            .map(|mb| mb * 1024 * 1024);
+        Self::open(dir, max_bytes)
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    fn read_index(dir: &Path) -> anyhow::Result<Index> {
+        match fs::read_to_string(dir.join("index.json")) {
+            Ok(json) => Ok(serde_json::from_str(&json).unwrap_or_else(|e| {
+                println!("Asset cache index is broken, starting again: {e}");
+                Index::default()
+            })),
+            Err(e) if e.kind() == ErrorKind::NotFound => Ok(Index::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// a hash from the index could have been edited by hand, so it's checked before it's
+    /// turned into a path
+    fn object_path(&self, hash: &str) -> anyhow::Result<PathBuf> {
+        if hash.len() != 16 || !hash.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(anyhow!("{hash:?} isn't an asset cache hash"));
+        }
+        Ok(self.dir.join("objects").join(&hash[..2]).join(hash))
+    }
+
+    /// the body `entry` points at, if it's there and intact
+    fn read_object(&self, entry: &Entry) -> Option<Vec<u8>> {
+        let body = fs::read(self.object_path(&entry.hash).ok()?).ok()?;
+        (body.len() as u64 == entry.size && content_hash(&body) == entry.hash).then_some(body)
+    }
+
+    /// runs `change` on the index on disk while holding the lock, then saves it. uses made
+    /// since the last save are written at the same time
+    fn update<T>(&self, change: impl FnOnce(&mut Index) -> anyhow::Result<T>) -> anyhow::Result<T> {
+        let mut state = self.state.lock().unwrap();
+        let _lock = IndexLock::acquire(&self.dir)?;
+        let mut index = Self::read_index(&self.dir)?;
+        for (key, time) in state.touched.drain() {
+            if let Some(entry) = index.entries.get_mut(&key) {
+                entry.last_used = entry.last_used.max(time);
+            }
+        }
+        let out = change(&mut index)?;
+        write_atomic(
+            &self.dir.join("index.json"),
+            serde_json::to_string_pretty(&index)?.as_bytes(),
+        )?;
+        state.index = index;
+        Ok(out)
+    }
+
+    /// the entry for `key`, looking at the index on disk if we haven't seen it, in case
+    /// another process has added it
+    fn lookup(&self, key: &str) -> anyhow::Result<Option<Entry>> {
+        let mut state = self.state.lock().unwrap();
+        if let Some(entry) = state.index.entries.get(key) {
+            return Ok(Some(entry.clone()));
+        }
+        state.index = Self::read_index(&self.dir)?;
+        Ok(state.index.entries.get(key).cloned())
+    }
+
+    /// saves when things were last used
+    pub fn flush(&self) -> anyhow::Result<()> {
+        self.update(|_| Ok(()))
+    }
+
+    pub fn contains(&self, url: &str) -> bool {
+        self.lookup(url).ok().flatten().is_some()
+    }
+
+    /// the cached body for `url`, if there is one and it's intact. a damaged one is removed
+    pub fn get(&self, url: &str) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(entry) = self.lookup(url)? else {
+            return Ok(None);
+        };
+        if let Some(body) = self.read_object(&entry) {
+            self.state
+                .lock()
+                .unwrap()
+                .touched
+                .insert(url.to_string(), now());
+            return Ok(Some(body));
+        }
+        println!("Cached copy of {url} is damaged, dropping it");
+        self.update(|index| {
+            // unless someone else has already put something new there
+            if index.entries.get(url).is_some_and(|e| e.hash == entry.hash) {
+                self.remove_entry(index, url)?;
+            }
+            Ok(())
+        })?;
+        Ok(None)
+    }
+
+    pub fn insert(&self, url: &str, body: &[u8]) -> anyhow::Result<()> {
+        let hash = content_hash(body);
+        let path = self.object_path(&hash)?;
+        self.update(|index| {
+            // rewritten even if it's there, in case it's the damaged copy of this body
+            let intact = fs::read(&path).is_ok_and(|b| b == body);
+            if !intact {
+                write_atomic(&path, body)?;
+            }
+            let time = now();
+            let entry = Entry {
+                hash: hash.clone(),
+                size: body.len() as u64,
+                fetched_at: time,
+                last_used: time,
+            };
+            if let Some(old) = index.entries.insert(url.to_string(), entry) {
+                if old.hash != hash {
+                    self.delete_object_if_unused(index, &old.hash)?;
+                }
+            }
+            if let Some(max) = self.max_bytes {
+                self.evict(index, max)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// the cached body, or a fresh one from `fetcher` once it's passed `validate`
+    pub fn get_or_fetch(
+        &self,
+        fetcher: &dyn Fetcher,
+        url: &str,
+        offline: bool,
+        validate: Validate,
+    ) -> anyhow::Result<Vec<u8>> {
+        self.get_or_fetch_as(url, fetcher, url, offline, validate)
+    }
+
+    /// like `get_or_fetch` but cached under `key` instead of the url, for when the same url can
+    /// serve something new that the caller knows about
+    pub fn get_or_fetch_as(
+        &self,
+        key: &str,
+        fetcher: &dyn Fetcher,
+        url: &str,
+        offline: bool,
+        validate: Validate,
+    ) -> anyhow::Result<Vec<u8>> {
+        if let Some(body) = self.get(key)? {
+            return Ok(body);
+        }
+        if offline {
+            return Err(anyhow!("{url} isn't cached and we're offline"));
+        }
+        let resp = fetcher.get(url)?.error_for_status(url)?;
+        if !resp.is_complete() {
+            return Err(anyhow!("{url} was cut off part way through"));
+        }
+        validate(&resp.body).map_err(|e| anyhow!("{url} isn't valid: {e}"))?;
+        self.insert(key, &resp.body)?;
+        Ok(resp.body)
+    }
+
+    fn delete_object_if_unused(&self, index: &Index, hash: &str) -> anyhow::Result<()> {
+        let Ok(path) = self.object_path(hash) else {
+            return Ok(());
+        };
+        if !index.entries.values().any(|e| e.hash == hash) && path.exists() {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+
+    fn remove_entry(&self, index: &mut Index, url: &str) -> anyhow::Result<bool> {
+        match index.entries.remove(url) {
+            Some(entry) => {
+                self.delete_object_if_unused(index, &entry.hash)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    pub fn remove(&self, url: &str) -> anyhow::Result<bool> {
+        self.update(|index| self.remove_entry(index, url))
+    }
+
+    /// every url and its entry, sorted by url
+    pub fn entries(&self) -> anyhow::Result<Vec<(String, Entry)>> {
+        Ok(Self::read_index(&self.dir)?.entries.into_iter().collect())
+    }
+
+    /// bytes on disk. bodies shared by several urls are only counted once
+    pub fn total_size(&self) -> anyhow::Result<u64> {
+        Ok(Self::read_index(&self.dir)?.total_size())
+    }
+
+    fn evict(&self, index: &mut Index, max_bytes: u64) -> anyhow::Result<usize> {
+        let mut by_age: Vec<(u64, String)> = index
+            .entries
+            .iter()
+            .map(|(url, e)| (e.last_used, url.clone()))
+            .collect();
+        by_age.sort();
+        let mut evicted = 0;
+        for (_, url) in by_age {
+            if index.total_size() <= max_bytes {
+                break;
+            }
+            self.remove_entry(index, &url)?;
+            evicted += 1;
+        }
+        Ok(evicted)
+    }
+
+    /// drops least recently used entries until the cache fits in `max_bytes`. returns how many
+    pub fn prune(&self, max_bytes: u64) -> anyhow::Result<usize> {
+        self.update(|index| self.evict(index, max_bytes))
+    }
+
+    pub fn clear(&self) -> anyhow::Result<()> {
+        self.update(|index| {
+            index.entries.clear();
+            fs::remove_dir_all(self.dir.join("objects"))?;
+            fs::create_dir_all(self.dir.join("objects"))?;
+            Ok(())
+        })
+    }
+
+    /// re-hashes every body, dropping damaged entries and deleting objects nothing uses
+    pub fn verify(&self) -> anyhow::Result<VerifyReport> {
+        self.update(|index| {
+            let mut report = VerifyReport::default();
+            let urls: Vec<String> = index.entries.keys().cloned().collect();
+            for url in urls {
+                if self.read_object(&index.entries[&url]).is_some() {
+                    report.ok += 1;
+                } else {
+                    self.remove_entry(index, &url)?;
+                    report.corrupt.push(url);
+                }
+            }
+            let used: HashSet<&str> = index.entries.values().map(|e| e.hash.as_str()).collect();
+            for shard in fs::read_dir(self.dir.join("objects"))? {
+                let shard = shard?.path();
+                if !shard.is_dir() {
+                    continue;
+                }
+                for object in fs::read_dir(shard)? {
+                    let object = object?;
+                    // this includes temporary files left by a process that died mid-write
+                    if !used.contains(object.file_name().to_string_lossy().as_ref()) {
+                        fs::remove_file(object.path())?;
+                        report.orphans += 1;
+                    }
+                }
+            }
+            Ok(report)
+        })
+    }
+}
+
+impl Drop for AssetCache {
+    fn drop(&mut self) {
+        if self.state.lock().unwrap().touched.is_empty() {
+            return;
+        }
+        if let Err(e) = self.flush() {
+            println!("Error saving asset cache index: {e}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "image_misc_asset_cache_{name}_{}",
+            std::process::id()
+        ));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn caches_sharing_a_dir_keep_each_others_entries() {
+        let dir = dir("shared");
+        let first = AssetCache::open(&dir, None).unwrap();
+        let second = AssetCache::open(&dir, None).unwrap();
+        first.insert("a", b"first").unwrap();
+        second.insert("b", b"second").unwrap();
+        first.get("a").unwrap();
+        drop(first);
+        assert_eq!(second.get("a").unwrap().as_deref(), Some(&b"first"[..]));
+        let keys: Vec<String> = AssetCache::open(&dir, None)
+            .unwrap()
+            .entries()
+            .unwrap()
+            .into_iter()
+            .map(|(k, _)| k)
+            .collect();
+        assert_eq!(keys, ["a", "b"]);
+    }
+
+    #[test]
+    fn inserting_the_same_body_at_once_works_every_time() {
+        let dir = Arc::new(dir("same_body"));
+        let threads: Vec<_> = (0..8)
+            .map(|n| {
+                let dir = dir.clone();
+                // a cache each, like separate processes would have
+                std::thread::spawn(move || {
+                    let cache = AssetCache::open(dir.as_path(), None).unwrap();
+                    cache.insert(&format!("url {n}"), b"the same body").unwrap();
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        let cache = AssetCache::open(dir.as_path(), None).unwrap();
+        assert_eq!(cache.entries().unwrap().len(), 8);
+        assert_eq!(cache.total_size().unwrap(), 13);
+        let report = cache.verify().unwrap();
+        assert_eq!((report.ok, report.corrupt.len(), report.orphans), (8, 0, 0));
+    }
+
+    #[test]
+    fn damaged_bodies_are_dropped_not_returned() {
+        let dir = dir("damaged");
+        let cache = AssetCache::open(&dir, None).unwrap();
+        cache.insert("a", b"the whole thing").unwrap();
+        let hash = content_hash(b"the whole thing");
+        fs::write(cache.object_path(&hash).unwrap(), b"the wh").unwrap();
+        assert_eq!(cache.get("a").unwrap(), None);
+        assert!(!cache.contains("a"));
+    }
+
+    #[test]
+    fn hand_edited_hashes_are_errors_not_panics() {
+        let dir = dir("bad_hash");
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(
+            dir.join("index.json"),
+            r#"{"entries": {"a": {"hash": "x", "size": 1, "fetched_at": 0, "last_used": 0}}}"#,
+        )
+        .unwrap();
+        let cache = AssetCache::open(&dir, None).unwrap();
+        assert_eq!(cache.get("a").unwrap(), None);
+        assert!(cache.entries().unwrap().is_empty());
+    }
+
+    #[test]
+    fn least_recently_used_goes_first_when_over_the_limit() {
+        let dir = dir("evict");
+        let cache = AssetCache::open(&dir, Some(10)).unwrap();
+        cache.insert("a", b"aaaaaa").unwrap();
+        cache.insert("b", b"bbbbbb").unwrap();
+        assert!(!cache.contains("a"));
+        assert!(cache.contains("b"));
+        assert_eq!(cache.total_size().unwrap(), 6);
+    }
+}
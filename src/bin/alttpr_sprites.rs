@@ -1,37 +1,277 @@
 use std::{
+    collections::HashSet,
     fs::{self, create_dir_all},
-    io::{Cursor, Read as _},
-    path::Path,
+    io::Cursor,
+    path::{Path, PathBuf},
 };
 
-use image::{
-    io::Reader, DynamicImage, GenericImage, GenericImageView, ImageFormat, Pixel, Rgb, Rgba,
-};
+use image::{io::Reader, DynamicImage, ImageFormat};
+#[cfg(feature = "rich_sprites")]
+use image::{Rgba, RgbaImage};
+use image_misc::asset_cache::{validate_image, AssetCache};
+use image_misc::download::{download_all, Download, DownloadConfig};
+use image_misc::fetch::Fetcher;
+#[cfg(feature = "rich_sprites")]
+use image_misc::gif::{write_gif, FrameDuration};
+#[cfg(feature = "rich_sprites")]
+use image_misc::pixel_scale::integer_scale;
+use image_misc::pixel_scale::ScaleFilter;
+use image_misc::zspr::PoseTable;
+#[cfg(feature = "rich_sprites")]
+use image_misc::zspr::{Mail, Zspr};
+#[cfg(feature = "rich_sprites")]
 use imageproc::drawing::text_size;
-use reqwest::blocking::Client;
 
+use crate::card::CardLayout;
+use crate::gallery::{Gallery, GallerySort};
 use crate::sprites::get_sprites_metadata;
 use clap::Parser;
+use regex::Regex;
 
 #[derive(Parser, Debug)]
 struct Args {
+    /// deprecated and ignored. the sprite list used to be cached under `sprites_<username>`; it's
+    /// now always in the shared cache dir
+    #[arg(long, hide = true)]
+    username: Option<String>,
+    /// ignore the cached sprite list and fetch it again
+    #[arg(long)]
+    refresh: bool,
+    /// never touch the network; fails if anything needed isn't cached
+    #[arg(long, conflicts_with = "refresh")]
+    offline: bool,
+    /// how long the cached sprite list is used before checking for a new one
+    #[arg(long, default_value_t = 24)]
+    cache_ttl_hours: u64,
+    /// where the sprite list comes from. handy for pointing at a local server
+    #[arg(long, default_value = "https://alttpr.com/sprites")]
+    sprites_url: String,
+    /// most downloads at once
+    #[arg(long, default_value_t = 4)]
+    concurrency: usize,
+    /// how many times a download is retried after a server error or timeout
+    #[arg(long, default_value_t = 4)]
+    max_retries: u32,
+    /// draw each sprite's `--pose` from its ZSPR instead of using the preview png
+    #[cfg(feature = "rich_sprites")]
+    #[arg(long)]
+    from_zspr: bool,
+    /// pose table json (animation -> direction -> frames) for `--from-zspr` and `--sheets`.
+    /// the standard table in assets/poses.json if not given
+    #[cfg(feature = "rich_sprites")]
+    #[arg(long)]
+    poses: Option<PathBuf>,
+    #[cfg(feature = "rich_sprites")]
+    #[arg(long, default_value = "Stand")]
+    pose: String,
+    #[cfg(feature = "rich_sprites")]
+    #[arg(long, default_value = "down")]
+    direction: String,
+    /// green, blue, red or bunny
+    #[cfg(feature = "rich_sprites")]
+    #[arg(long, default_value = "green")]
+    mail: Mail,
+    /// also draw a sheet of every pose in `--sheet-animations` and an animated walk cycle for
+    /// each sprite, from its ZSPR
+    #[cfg(feature = "rich_sprites")]
+    #[arg(long)]
+    sheets: bool,
+    #[cfg(feature = "rich_sprites")]
+    #[arg(long, value_delimiter = ',', default_value = "Walk,Sword,Item")]
+    sheet_animations: Vec<String>,
+    #[cfg(feature = "rich_sprites")]
+    #[arg(long, default_value = "Walk")]
+    walk_animation: String,
+    /// how many times bigger than the real sprite the sheets and gifs are
+    #[cfg(feature = "rich_sprites")]
+    #[arg(long, default_value_t = 4)]
+    sheet_scale: u32,
+    /// card look for the upscaled sprites: `default`, `transparent`, `greenscreen` or a path to
+    /// a json layout
+    #[arg(long, default_value = "default")]
+    card_layout: String,
+    /// also lay every sprite out in a grid with its name underneath, in `./sprites/gallery/`
+    /// along with an index.html linking to each card
+    #[arg(long)]
+    gallery: bool,
+    #[arg(long, default_value_t = 10)]
+    gallery_columns: u32,
+    /// split the gallery into sheets of this many sprites
     #[arg(long)]
-    username: String,
+    gallery_per_page: Option<usize>,
+    /// `name` or `author`
+    #[arg(long, default_value = "name")]
+    gallery_sort: GallerySort,
+    /// how many times bigger than the real sprite the gallery is
+    #[arg(long, default_value_t = 3)]
+    gallery_scale: u32,
+    /// upscale the gallery with this instead of `--gallery-scale` times nearest neighbour, e.g.
+    /// `scale3x` or `hq2x`
+    #[arg(long)]
+    gallery_scale_filter: Option<ScaleFilter>,
+    #[command(flatten)]
+    filter: SpriteFilter,
+    /// list the sprites that would be processed and stop
+    #[arg(long)]
+    dry_run: bool,
+}
+
+// which sprites get processed. a sprite has to match at least one of the name filters (or
+// `(?i)link` if none are given) and every one of the metadata filters
+#[derive(clap::Args, Debug)]
+struct SpriteFilter {
+    /// every sprite, whatever its name
+    #[arg(long, conflicts_with_all = ["name_regex", "name", "names_file"])]
+    all: bool,
+    /// sprites whose name matches this regex
+    #[arg(long)]
+    name_regex: Option<Regex>,
+    /// a sprite with exactly this name, ignoring case. can be given more than once
+    #[arg(long)]
+    name: Vec<String>,
+    /// a file of sprite names, one per line. blank lines and lines starting with `#` are skipped
+    #[arg(long)]
+    names_file: Option<PathBuf>,
+    /// only sprites with this tag. can be given more than once, and then sprites need all of them
+    #[cfg(feature = "rich_sprites")]
+    #[arg(long)]
+    tag: Vec<String>,
+    /// only sprites whose author contains this, ignoring case
+    #[cfg(feature = "rich_sprites")]
+    #[arg(long)]
+    author: Option<String>,
+    /// only sprites allowed for this usage, e.g. `smz3`
+    #[cfg(feature = "rich_sprites")]
+    #[arg(long)]
+    usage: Option<String>,
+}
+
+/// `SpriteFilter` with the names file read and the default applied
+struct NameFilter {
+    all: bool,
+    regex: Option<Regex>,
+    /// lowercased
+    names: HashSet<String>,
+}
+
+impl SpriteFilter {
+    fn name_filter(&self) -> anyhow::Result<NameFilter> {
+        let mut names: HashSet<String> = self.name.iter().map(|n| n.to_lowercase()).collect();
+        if let Some(path) = &self.names_file {
+            let list = fs::read_to_string(path)
+                .map_err(|e| anyhow::anyhow!("Error reading {}: {e}", path.display()))?;
+            names.extend(
+                list.lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .map(|l| l.to_lowercase()),
+            );
+        }
+        let regex = if self.name_regex.is_none() && names.is_empty() && !self.all {
+            Some(Regex::new("(?i)link")?)
+        } else {
+            self.name_regex.clone()
+        };
+        Ok(NameFilter {
+            all: self.all,
+            regex,
+            names,
+        })
+    }
+
+    /// whether the sprite passes `--tag`, `--author` and `--usage`
+    #[cfg(feature = "rich_sprites")]
+    fn metadata_matches(&self, sprite: &Sprite) -> bool {
+        let has = |list: &[String], want: &str| list.iter().any(|l| l.eq_ignore_ascii_case(want));
+        self.tag.iter().all(|t| has(&sprite.tags, t))
+            && self
+                .author
+                .as_ref()
+                .is_none_or(|a| sprite.author.to_lowercase().contains(&a.to_lowercase()))
+            && self.usage.as_ref().is_none_or(|u| has(&sprite.usage, u))
+    }
+
+    #[cfg(not(feature = "rich_sprites"))]
+    fn metadata_matches(&self, _sprite: &Sprite) -> bool {
+        true
+    }
+}
+
+impl NameFilter {
+    fn matches(&self, name: &str) -> bool {
+        self.all
+            || self.names.contains(&name.to_lowercase())
+            || self.regex.as_ref().is_some_and(|r| r.is_match(name))
+    }
 }
 
 fn main() -> anyhow::Result<()> {
     println!("hello");
     let args = Args::parse();
-    let c = reqwest::blocking::Client::builder().build()?;
-    let metadata = get_sprites_metadata(&c, &args)?;
-    for m in metadata
+    if args.username.is_some() {
+        println!("--username is deprecated and does nothing");
+    }
+    let fetcher = image_misc::fetch::from_env()?;
+    let c = fetcher.as_ref();
+    let cache = std::sync::Arc::new(AssetCache::from_env()?);
+    let metadata = get_sprites_metadata(c, &args)?;
+    let names = args.filter.name_filter()?;
+    let selected: Vec<&Sprite> = metadata
         .iter()
-        .filter(|s| s.name.to_lowercase().contains("link"))
-    {
-        if let Err(e) = m.upscale_sprite(&c) {
-            println!("Error fetching {}: {e}", m.name);
+        .filter(|s| names.matches(&s.name) && args.filter.metadata_matches(s))
+        .collect();
+    println!("{} of {} sprites selected", selected.len(), metadata.len());
+    if args.dry_run {
+        for m in &selected {
+            println!("{}", m.name);
+        }
+        return Ok(());
+    }
+    let layout = CardLayout::load(&args.card_layout)?;
+    #[cfg(feature = "rich_sprites")]
+    let poses = match &args.poses {
+        Some(path) => PoseTable::from_file(path)?,
+        None => PoseTable::standard()?,
+    };
+    #[cfg(not(feature = "rich_sprites"))]
+    let poses = PoseTable::default();
+    if !args.offline {
+        let downloads = selected.iter().flat_map(|m| m.downloads(&args)).collect();
+        let config = DownloadConfig {
+            concurrency: args.concurrency,
+            max_retries: args.max_retries,
+            ..DownloadConfig::default()
+        };
+        let summary = download_all(fetcher.clone(), cache.clone(), downloads, &config)?;
+        println!("Downloads: {summary}");
+    }
+    let mut gallery_entries = Vec::new();
+    for m in selected {
+        match m.upscale_sprite(c, &cache, &args, &poses, &layout) {
+            Ok(entry) => gallery_entries.push(entry),
+            Err(e) => println!("Error fetching {}: {e}", m.name),
+        }
+        #[cfg(feature = "rich_sprites")]
+        if args.sheets {
+            if let Err(e) = m.render_animations(c, &cache, &args, &poses) {
+                println!("Error drawing animations for {}: {e}", m.name);
+            }
         }
     }
+    if args.gallery {
+        let gallery = Gallery {
+            columns: args.gallery_columns,
+            per_page: args.gallery_per_page,
+            scale: args
+                .gallery_scale_filter
+                .unwrap_or(ScaleFilter::Integer(args.gallery_scale.max(1))),
+            sort: args.gallery_sort,
+        };
+        gallery.write(
+            &mut gallery_entries,
+            &Path::new(sprites::CACHE_DIR).join("gallery"),
+        )?;
+    }
     Ok(())
 }
 
@@ -39,47 +279,101 @@ fn main() -> anyhow::Result<()> {
 struct Sprite {
     name: String,
     #[cfg(feature = "rich_sprites")]
+    #[serde(default)]
     author: String,
     #[cfg(feature = "rich_sprites")]
+    #[serde(default)]
     version: u32,
     #[cfg(feature = "rich_sprites")]
     file: String,
     preview: String,
     #[cfg(feature = "rich_sprites")]
+    #[serde(default)]
     tags: Vec<String>,
+    /// e.g. `smz3` or `commercial`
     #[cfg(feature = "rich_sprites")]
+    #[serde(default)]
     usage: Vec<String>,
 }
 
 impl Sprite {
     fn preview_filename(&self) -> &str {
-        self.preview.split('/').last().unwrap()
+        self.preview.split('/').next_back().unwrap()
     }
 
     // these are 16x24
-    fn get_preview_data(&self, c: &Client) -> anyhow::Result<Vec<u8>> {
-        let cache_path = Path::new("./sprites").join(&self.preview_filename());
-        if cache_path.exists() {
-            Ok(fs::read(cache_path)?)
-        } else {
-            let req = c.get(&self.preview).build()?;
-            let mut resp = c.execute(req)?;
-            if !resp.status().is_success() {
-                return Err(anyhow::anyhow!(
-                    "Error fetching sprite preview: {}",
-                    resp.status()
-                ));
+    fn get_preview_data(
+        &self,
+        c: &dyn Fetcher,
+        cache: &AssetCache,
+        offline: bool,
+    ) -> anyhow::Result<Vec<u8>> {
+        cache
+            .get_or_fetch(c, &self.preview, offline, validate_image)
+            .map_err(|e| anyhow::anyhow!("Error getting preview for {}: {e}", self.name))
+    }
+
+    #[cfg(feature = "rich_sprites")]
+    fn zspr_cache_key(&self) -> String {
+        // the version's in the key so an updated sprite doesn't come out of the cache
+        format!("{}#v{}", self.file, self.version)
+    }
+
+    /// everything this run needs for the sprite, to fetch up front
+    fn downloads(&self, args: &Args) -> Vec<Download> {
+        #[cfg(feature = "rich_sprites")]
+        {
+            let mut downloads = Vec::new();
+            if args.from_zspr || args.sheets {
+                downloads
+                    .push(Download::new(&self.file, validate_zspr).with_key(self.zspr_cache_key()));
             }
-            let mut buf = Vec::<u8>::with_capacity(resp.content_length().unwrap_or(69) as usize);
-            resp.read_to_end(&mut buf)?;
-            std::fs::write(cache_path, &buf)
-                .map_err(|e| anyhow::anyhow!("Error caching preview data: {e}"))?;
-            Ok(buf)
+            if !args.from_zspr {
+                downloads.push(Download::new(&self.preview, validate_image));
+            }
+            downloads
+        }
+        #[cfg(not(feature = "rich_sprites"))]
+        {
+            let _ = args;
+            vec![Download::new(&self.preview, validate_image)]
         }
     }
 
-    fn upscale_sprite(&self, c: &Client) -> anyhow::Result<()> {
-        let img_data = self.get_preview_data(c)?;
+    #[cfg(feature = "rich_sprites")]
+    fn get_zspr(&self, c: &dyn Fetcher, cache: &AssetCache, offline: bool) -> anyhow::Result<Zspr> {
+        let data = cache
+            .get_or_fetch_as(
+                &self.zspr_cache_key(),
+                c,
+                &self.file,
+                offline,
+                validate_zspr,
+            )
+            .map_err(|e| anyhow::anyhow!("Error getting ZSPR for {}: {e}", self.name))?;
+        Zspr::parse(&data)
+    }
+
+    /// the 16x24 preview, or with `--from-zspr` the chosen pose drawn from the sprite's ZSPR
+    fn sprite_image(
+        &self,
+        c: &dyn Fetcher,
+        cache: &AssetCache,
+        args: &Args,
+        poses: &PoseTable,
+    ) -> anyhow::Result<DynamicImage> {
+        #[cfg(feature = "rich_sprites")]
+        if args.from_zspr {
+            let frame = poses
+                .frames(&args.pose, &args.direction)?
+                .first()
+                .ok_or_else(|| anyhow::anyhow!("{} {} has no frames", args.pose, args.direction))?;
+            let zspr = self.get_zspr(c, cache, args.offline)?;
+            let palette = zspr.palette(args.mail, None);
+            return Ok(DynamicImage::ImageRgba8(zspr.render(frame, &palette)?));
+        }
+        let _ = poses;
+        let img_data = self.get_preview_data(c, cache, args.offline)?;
         let c = Cursor::new(img_data.as_slice());
         let ir = Reader::with_format(c, ImageFormat::Png);
         let image = ir.decode()?;
@@ -89,25 +383,25 @@ impl Sprite {
                 self.name
             ));
         }
-        let mut opaque = DynamicImage::new(24, 24, image.color());
-        for y in 0..24 {
-            for x in 0..4 {
-                opaque.put_pixel(x, y, Rgba([255, 255, 255, 255]));
-                opaque.put_pixel(24 - x - 1, y, Rgba([255, 255, 255, 255]));
-            }
-        }
-        for (x, y, mut rgba) in image.pixels() {
-            if let Some(alpha) = rgba.0.get(3) {
-                if *alpha == 0 {
-                    rgba[0] = u8::MAX;
-                    rgba[1] = u8::MAX;
-                    rgba[2] = u8::MAX;
-                }
-                rgba[3] = u8::MAX;
-            }
-            opaque.put_pixel(x + 4, y, rgba);
-        }
-        let mut bigger = opaque.resize(16 * 16, 24 * 16, image::imageops::FilterType::Nearest);
+        Ok(image)
+    }
+
+    fn upscale_sprite(
+        &self,
+        c: &dyn Fetcher,
+        cache: &AssetCache,
+        args: &Args,
+        poses: &PoseTable,
+        layout: &CardLayout,
+    ) -> anyhow::Result<gallery::Entry> {
+        let image = self.sprite_image(c, cache, args, poses)?;
+        #[cfg(feature = "rich_sprites")]
+        let author = self.author.clone();
+        #[cfg(not(feature = "rich_sprites"))]
+        let author = String::new();
+        let byline = (!author.is_empty()).then(|| format!("by {author}"));
+        let card = layout.render(&image, &self.name, byline.as_deref())?;
+
         let base_path = Path::new(self.preview_filename());
         let embiggened_path_str = format!(
             "{}_big.{}",
@@ -116,94 +410,901 @@ impl Sprite {
         );
         let big_dir = Path::new("./sprites/big/");
         let embiggened_path = big_dir.join(&embiggened_path_str);
+        create_dir_all(big_dir)?;
+        card.save(&embiggened_path)?;
+        Ok(gallery::Entry {
+            name: self.name.clone(),
+            author,
+            image,
+            card: embiggened_path,
+        })
+    }
+}
+
+#[cfg(feature = "rich_sprites")]
+impl Sprite {
+    fn output_stem(&self) -> &str {
+        let filename = self.preview_filename();
+        filename.rsplit_once('.').map_or(filename, |(stem, _)| stem)
+    }
+
+    fn render_animations(
+        &self,
+        c: &dyn Fetcher,
+        cache: &AssetCache,
+        args: &Args,
+        poses: &PoseTable,
+    ) -> anyhow::Result<()> {
+        let zspr = self.get_zspr(c, cache, args.offline)?;
+        let palette = zspr.palette(args.mail, None);
+        self.pose_sheet(&zspr, poses, &palette, args)?;
+        self.walk_gif(&zspr, poses, &palette, args)
+    }
+
+    /// one row per animation and direction, every frame of it left to right, with the name
+    /// of the row down the left
+    fn pose_sheet(
+        &self,
+        zspr: &Zspr,
+        poses: &PoseTable,
+        palette: &[Rgba<u8>; 16],
+        args: &Args,
+    ) -> anyhow::Result<()> {
+        let mut rows = vec![];
+        for animation in &args.sheet_animations {
+            let directions = poses.directions(animation);
+            if directions.is_empty() {
+                println!("No {animation} in the pose table, leaving it off the sheet");
+            }
+            for direction in directions {
+                let frames = zspr.render_animation(poses.frames(animation, direction)?, palette)?;
+                rows.push((format!("{animation} {direction}"), frames));
+            }
+        }
+        if rows.is_empty() {
+            return Err(anyhow::anyhow!("Nothing to put on the sheet"));
+        }
 
-        // ok and lets write the name on it
         let font = ab_glyph::FontRef::try_from_slice(include_bytes!("../../fonts/ARIALBD.TTF"))?;
-        let h = bigger.height() as i32;
+        let scale = args.sheet_scale.max(1);
+        let spacing = 2 * scale;
+        let label_size = 6.0 * scale as f32;
+        let cell_width = rows
+            .iter()
+            .flat_map(|(_, f)| f.iter().map(|(i, _)| i.width()))
+            .max()
+            .unwrap_or(0)
+            * scale;
+        let cell_height = rows
+            .iter()
+            .flat_map(|(_, f)| f.iter().map(|(i, _)| i.height()))
+            .max()
+            .unwrap_or(0)
+            * scale;
+        let label_width = rows
+            .iter()
+            .map(|(label, _)| text_size(label_size, &font, label).0)
+            .max()
+            .unwrap_or(0);
+        let columns = rows.iter().map(|(_, f)| f.len()).max().unwrap_or(0) as u32;
+
+        let width = spacing + label_width + spacing + columns * (cell_width + spacing);
+        let height = spacing + rows.len() as u32 * (cell_height + spacing);
+        let mut sheet = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+        for (r, (label, frames)) in rows.iter().enumerate() {
+            let y = spacing + r as u32 * (cell_height + spacing);
+            imageproc::drawing::draw_text_mut(
+                &mut sheet,
+                Rgba([32, 32, 32, 255]),
+                spacing as i32,
+                (y + cell_height / 2) as i32 - label_size as i32 / 2,
+                label_size,
+                &font,
+                label,
+            );
+            for (n, (frame, _)) in frames.iter().enumerate() {
+                let x = spacing + label_width + spacing + n as u32 * (cell_width + spacing);
+                let frame = integer_scale(frame, scale);
+                image::imageops::overlay(&mut sheet, &frame, x as i64, y as i64);
+            }
+        }
+
+        let dir = Path::new(sprites::CACHE_DIR).join("sheets");
+        create_dir_all(&dir)?;
+        sheet.save(dir.join(format!("{}_{}_sheet.png", self.output_stem(), args.mail)))?;
+        Ok(())
+    }
+
+    /// every direction of the walk cycle side by side, one gif frame per game frame
+    fn walk_gif(
+        &self,
+        zspr: &Zspr,
+        poses: &PoseTable,
+        palette: &[Rgba<u8>; 16],
+        args: &Args,
+    ) -> anyhow::Result<()> {
+        let animation = &args.walk_animation;
+        let directions = poses.directions(animation);
+        if directions.is_empty() {
+            return Err(anyhow::anyhow!("No {animation} in the pose table"));
+        }
+        // each direction as one image per game frame
+        let mut cycles = vec![];
+        for direction in directions {
+            let frames = zspr.render_animation(poses.frames(animation, direction)?, palette)?;
+            let cycle: Vec<RgbaImage> = frames
+                .into_iter()
+                .flat_map(|(image, held)| std::iter::repeat_n(image, held as usize))
+                .collect();
+            if !cycle.is_empty() {
+                cycles.push(cycle);
+            }
+        }
+        let scale = args.sheet_scale.max(1);
+        let spacing = 2 * scale;
+        let cell_width = cycles.iter().map(|c| c[0].width()).max().unwrap_or(0) * scale;
+        let cell_height = cycles.iter().map(|c| c[0].height()).max().unwrap_or(0) * scale;
+        let length = cycles.iter().map(|c| c.len()).max().unwrap_or(0);
+        let width = spacing + cycles.len() as u32 * (cell_width + spacing);
+        let height = cell_height + 2 * spacing;
+
+        // shorter cycles just loop until the longest one is done
+        let gif_frames = (0..length).map(|n| {
+            let mut frame = RgbaImage::from_pixel(width, height, Rgba([255, 255, 255, 255]));
+            for (d, cycle) in cycles.iter().enumerate() {
+                let x = spacing + d as u32 * (cell_width + spacing);
+                let pose = integer_scale(&cycle[n % cycle.len()], scale);
+                image::imageops::overlay(&mut frame, &pose, x as i64, spacing as i64);
+            }
+            DynamicImage::ImageRgba8(frame)
+        });
+
+        let dir = Path::new(sprites::CACHE_DIR).join("gifs");
+        create_dir_all(&dir)?;
+        let out =
+            fs::File::create(dir.join(format!("{}_{}_walk.gif", self.output_stem(), args.mail)))?;
+        write_gif(gif_frames, out, FrameDuration::Fps60)
+    }
+}
+
+#[cfg(feature = "rich_sprites")]
+fn validate_zspr(body: &[u8]) -> anyhow::Result<()> {
+    Zspr::parse(body).map(|_| ())
+}
+
+mod card {
+    use std::path::Path;
+
+    use ab_glyph::FontRef;
+    use image::{imageops::overlay, DynamicImage, Rgba, RgbaImage};
+    use image_misc::{
+        fonts::bundled_font,
+        pixel_scale::{upscale, ScaleFilter},
+    };
+    use imageproc::{
+        drawing::{draw_hollow_rect_mut, draw_text_mut, text_size},
+        rect::Rect,
+    };
+    use serde::Deserialize;
+
+    #[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+    #[serde(rename_all = "snake_case")]
+    pub enum TextPosition {
+        /// drawn over the bottom of the sprite
+        Overlay,
+        /// in a band of background above the sprite
+        Above,
+        /// in a band of background below the sprite
+        Below,
+    }
+
+    #[derive(Deserialize, Debug, Clone)]
+    pub struct Stroke {
+        pub colour: [u8; 4],
+        /// in card pixels
+        pub width: u32,
+    }
+
+    /// smallest a byline is drawn before it's left off instead
+    const MIN_BYLINE_SIZE: f32 = 12.0;
 
-        // returns (x offset, font scale)
-        let situate_text = |width: u32, text: &str| -> anyhow::Result<(i32, f32)> {
-            for trial_size in [64.0, 56.0, 48.0, 32.0, 24.0] {
-                let (w, _) = text_size(trial_size, &font, text);
+    /// how the upscaled sprite cards look. read from json, where anything left out keeps its
+    /// default. the defaults are the original white card with red text
+    #[derive(Deserialize, Debug, Clone)]
+    #[serde(default)]
+    pub struct CardLayout {
+        /// canvas size in sprite pixels, before scaling. the sprite is centred on it. defaults
+        /// to a square that fits the sprite
+        pub canvas: Option<[u32; 2]>,
+        /// behind the sprite and in its transparent pixels. an alpha of 0 leaves them transparent
+        pub background: [u8; 4],
+        pub scale: u32,
+        /// upscales the canvas instead of `scale` times nearest neighbour, e.g. `"scale2x"` or
+        /// `"hq2x"`
+        pub scale_filter: Option<ScaleFilter>,
+        pub text_colour: [u8; 4],
+        pub byline_colour: [u8; 4],
+        pub outline: Option<Stroke>,
+        /// one of the bundled fonts, e.g. `arial_bold`
+        pub font: String,
+        /// tried biggest first until the name fits
+        pub font_sizes: Vec<f32>,
+        /// the author byline is never bigger than this. a byline that doesn't fit at any of
+        /// `font_sizes` keeps shrinking down to `MIN_BYLINE_SIZE`, and is left off if it still
+        /// doesn't fit
+        pub byline_max_size: f32,
+        pub text_position: TextPosition,
+        pub border: Option<Stroke>,
+    }
+
+    impl Default for CardLayout {
+        fn default() -> Self {
+            Self {
+                canvas: None,
+                background: [255, 255, 255, 255],
+                scale: 16,
+                scale_filter: None,
+                text_colour: [222, 32, 32, 255],
+                byline_colour: [64, 64, 64, 255],
+                outline: None,
+                font: "arial_bold".to_string(),
+                font_sizes: vec![64.0, 56.0, 48.0, 32.0, 24.0],
+                byline_max_size: 32.0,
+                text_position: TextPosition::Overlay,
+                border: None,
+            }
+        }
+    }
+
+    impl CardLayout {
+        /// `default`, `transparent` or `greenscreen`
+        pub fn preset(name: &str) -> anyhow::Result<Self> {
+            let default = Self::default();
+            match name {
+                "default" => Ok(default),
+                "transparent" => Ok(Self {
+                    background: [0, 0, 0, 0],
+                    text_colour: [255, 255, 255, 255],
+                    byline_colour: [200, 200, 200, 255],
+                    outline: Some(Stroke {
+                        colour: [0, 0, 0, 255],
+                        width: 3,
+                    }),
+                    ..default
+                }),
+                "greenscreen" => Ok(Self {
+                    background: [0, 255, 0, 255],
+                    text_colour: [255, 255, 255, 255],
+                    byline_colour: [255, 255, 255, 255],
+                    outline: Some(Stroke {
+                        colour: [0, 0, 0, 255],
+                        width: 3,
+                    }),
+                    text_position: TextPosition::Below,
+                    ..default
+                }),
+                other => Err(anyhow::anyhow!("Unknown card layout {other}")),
+            }
+        }
+
+        /// a preset name, or the path to a json file
+        pub fn load(name_or_path: &str) -> anyhow::Result<Self> {
+            let path = Path::new(name_or_path);
+            if path.extension().is_some_and(|e| e == "json") || path.exists() {
+                let json = std::fs::read_to_string(path)
+                    .map_err(|e| anyhow::anyhow!("Error reading {name_or_path}: {e}"))?;
+                return Ok(serde_json::from_str(&json)?);
+            }
+            Self::preset(name_or_path)
+        }
+
+        /// biggest of `sizes` that fits `text` in `width`, as (x offset to centre it, size)
+        fn situate_text(
+            &self,
+            font: &FontRef,
+            sizes: impl Iterator<Item = f32>,
+            width: u32,
+            text: &str,
+        ) -> Option<(i32, f32)> {
+            for trial_size in sizes {
+                let (w, _) = text_size(trial_size, font, text);
                 if w < width {
-                    let offset = (width - w) / 2;
+                    return Some((((width - w) / 2) as i32, trial_size));
+                }
+            }
+            None
+        }
+
+        fn draw_text(
+            &self,
+            card: &mut RgbaImage,
+            colour: [u8; 4],
+            (x, y): (i32, i32),
+            size: f32,
+            font: &FontRef,
+            text: &str,
+        ) {
+            if let Some(outline) = &self.outline {
+                let w = outline.width as i32;
+                for dy in -w..=w {
+                    for dx in -w..=w {
+                        if (dx, dy) != (0, 0) {
+                            draw_text_mut(
+                                card,
+                                Rgba(outline.colour),
+                                x + dx,
+                                y + dy,
+                                size,
+                                font,
+                                text,
+                            );
+                        }
+                    }
+                }
+            }
+            draw_text_mut(card, Rgba(colour), x, y, size, font, text);
+        }
+
+        pub fn render(
+            &self,
+            sprite: &DynamicImage,
+            name: &str,
+            byline: Option<&str>,
+        ) -> anyhow::Result<RgbaImage> {
+            let sprite = sprite.to_rgba8();
+            let side = sprite.width().max(sprite.height());
+            let [canvas_width, canvas_height] = self.canvas.unwrap_or([side, side]);
+            let (canvas_width, canvas_height) = (
+                canvas_width.max(sprite.width()),
+                canvas_height.max(sprite.height()),
+            );
+            let (pad_x, pad_y) = (
+                (canvas_width - sprite.width()) / 2,
+                (canvas_height - sprite.height()) / 2,
+            );
+            let mut canvas =
+                RgbaImage::from_pixel(canvas_width, canvas_height, Rgba(self.background));
+            for (x, y, px) in sprite.enumerate_pixels() {
+                if px.0[3] != 0 {
+                    let mut px = *px;
+                    px.0[3] = u8::MAX;
+                    canvas.put_pixel(x + pad_x, y + pad_y, px);
+                }
+            }
+            let filter = self
+                .scale_filter
+                .unwrap_or(ScaleFilter::Integer(self.scale.max(1)));
+            let bigger = upscale(&DynamicImage::ImageRgba8(canvas), filter).to_rgba8();
+
+            let font = bundled_font(&self.font)?;
+            let (name_x, name_size) = self
+                .situate_text(&font, self.font_sizes.iter().copied(), bigger.width(), name)
+                .ok_or_else(|| anyhow::anyhow!("Could not find a good size for {name}"))?;
+            let band = name_size as u32 + 8;
+            let (mut card, sprite_y, name_y) = match self.text_position {
+                TextPosition::Overlay => (
+                    bigger.clone(),
+                    0,
+                    bigger.height() as i32 - name_size as i32 - 4,
+                ),
+                TextPosition::Above => (
+                    RgbaImage::from_pixel(
+                        bigger.width(),
+                        bigger.height() + band,
+                        Rgba(self.background),
+                    ),
+                    band,
+                    4,
+                ),
+                TextPosition::Below => (
+                    RgbaImage::from_pixel(
+                        bigger.width(),
+                        bigger.height() + band,
+                        Rgba(self.background),
+                    ),
+                    0,
+                    (bigger.height() + 4) as i32,
+                ),
+            };
+            if self.text_position != TextPosition::Overlay {
+                overlay(&mut card, &bigger, 0, sprite_y as i64);
+            }
+            self.draw_text(
+                &mut card,
+                self.text_colour,
+                (name_x, name_y),
+                name_size,
+                &font,
+                name,
+            );
+
+            // the byline goes on the opposite edge to the name. it's extra, so one that's too
+            // long to fit is left off rather than costing the whole card
+            let sizes = self
+                .font_sizes
+                .iter()
+                .map(|s| s.min(self.byline_max_size))
+                .chain(
+                    (MIN_BYLINE_SIZE as u32..=self.byline_max_size as u32)
+                        .rev()
+                        .map(|s| s as f32),
+                );
+            let situated =
+                byline.and_then(|b| Some((b, self.situate_text(&font, sizes, card.width(), b)?)));
+            if let Some((byline, (x, size))) = situated {
+                let y = match self.text_position {
+                    TextPosition::Above => card.height() as i32 - size as i32 - 4,
+                    _ => 4,
+                };
+                self.draw_text(&mut card, self.byline_colour, (x, y), size, &font, byline);
+            }
+
+            if let Some(border) = &self.border {
+                for inset in 0..border.width.min(card.width() / 2).min(card.height() / 2) {
+                    let rect = Rect::at(inset as i32, inset as i32)
+                        .of_size(card.width() - inset * 2, card.height() - inset * 2);
+                    draw_hollow_rect_mut(&mut card, rect, Rgba(border.colour));
+                }
+            }
+            Ok(card)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        #[test]
+        fn a_byline_too_long_to_fit_is_left_off() {
+            let layout = CardLayout::default();
+            let sprite = DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 24, Rgba([0; 4])));
+            let without = layout.render(&sprite, "Link", None).unwrap();
+            let long = format!("by {}", "Someone Quite Long ".repeat(4));
+            assert_eq!(
+                layout.render(&sprite, "Link", Some(&long)).unwrap(),
+                without
+            );
+            // shorter than the name sizes allow, but fine once shrunk a bit more
+            let medium = format!("by {}", "x".repeat(40));
+            assert_ne!(
+                layout.render(&sprite, "Link", Some(&medium)).unwrap(),
+                without
+            );
+        }
+
+        #[test]
+        fn the_layout_picks_the_upscaler() {
+            let layout: CardLayout =
+                serde_json::from_str(r#"{"scale_filter": "scale3x", "font_sizes": [8]}"#).unwrap();
+            assert_eq!(layout.scale_filter, Some(ScaleFilter::Scale3x));
+            let sprite = DynamicImage::ImageRgba8(RgbaImage::from_pixel(16, 24, Rgba([0; 4])));
+            let card = layout.render(&sprite, "Link", None).unwrap();
+            assert_eq!(card.dimensions(), (24 * 3, 24 * 3));
+            assert!(serde_json::from_str::<CardLayout>(r#"{"scale_filter": "blurry"}"#).is_err());
+        }
+    }
+}
+
+mod gallery {
+    use std::fmt::Write as _;
+    use std::fs;
+    use std::path::{Path, PathBuf};
+    use std::str::FromStr;
+
+    use ab_glyph::FontRef;
+    use image::{imageops::overlay, DynamicImage, Rgba, RgbaImage};
+    use image_misc::{
+        fonts::bundled_font,
+        pixel_scale::{upscale, ScaleFilter},
+    };
+    use imageproc::drawing::{draw_text_mut, text_size};
+
+    const BACKGROUND: Rgba<u8> = Rgba([32, 32, 32, 255]);
+    const NAME_COLOUR: Rgba<u8> = Rgba([255, 255, 255, 255]);
+    const AUTHOR_COLOUR: Rgba<u8> = Rgba([160, 160, 160, 255]);
+    const NAME_SIZE: f32 = 16.0;
+    const AUTHOR_SIZE: f32 = 12.0;
+    const SPACING: u32 = 8;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum GallerySort {
+        Name,
+        /// then name
+        Author,
+    }
+
+    impl FromStr for GallerySort {
+        type Err = anyhow::Error;
+
+        /// `name` or `author`
+        fn from_str(s: &str) -> Result<Self, Self::Err> {
+            match s {
+                "name" => Ok(GallerySort::Name),
+                "author" => Ok(GallerySort::Author),
+                other => Err(anyhow::anyhow!("Unknown gallery sort {other}")),
+            }
+        }
+    }
+
+    /// one sprite that made it through `upscale_sprite`
+    pub struct Entry {
+        pub name: String,
+        /// empty if we don't know
+        pub author: String,
+        /// the sprite at its real size
+        pub image: DynamicImage,
+        /// its upscaled card
+        pub card: PathBuf,
+    }
+
+    pub struct Gallery {
+        pub columns: u32,
+        /// sprites per sheet. `None` puts them all on one
+        pub per_page: Option<usize>,
+        pub scale: ScaleFilter,
+        pub sort: GallerySort,
+    }
+
+    impl Gallery {
+        /// writes the sheets and `index.html` into `dir`, returning the sheets' paths
+        pub fn write(&self, entries: &mut [Entry], dir: &Path) -> anyhow::Result<Vec<PathBuf>> {
+            if entries.is_empty() {
+                return Ok(Vec::new());
+            }
+            entries.sort_by_cached_key(|e| {
+                let name = e.name.to_lowercase();
+                match self.sort {
+                    GallerySort::Name => (name, String::new()),
+                    GallerySort::Author => (e.author.to_lowercase(), name),
+                }
+            });
+            let name_font = bundled_font("arial_bold")?;
+            let author_font = bundled_font("arial")?;
+            fs::create_dir_all(dir)?;
+            // sheets from an earlier run with a different page size would be left lying around
+            for file in fs::read_dir(dir)? {
+                let path = file?.path();
+                let name = path.file_name().unwrap_or_default().to_string_lossy();
+                if name.starts_with("gallery") && name.ends_with(".png") {
+                    fs::remove_file(&path)?;
+                }
+            }
+            let per_page = self.per_page.unwrap_or(entries.len()).max(1);
+            let pages: Vec<&[Entry]> = entries.chunks(per_page).collect();
+            let mut paths = Vec::with_capacity(pages.len());
+            for (n, page) in pages.iter().enumerate() {
+                let path = if pages.len() == 1 {
+                    dir.join("gallery.png")
+                } else {
+                    dir.join(format!("gallery_{:02}.png", n + 1))
+                };
+                self.sheet(page, &name_font, &author_font).save(&path)?;
+                println!("Wrote {}", path.display());
+                paths.push(path);
+            }
+            let index = dir.join("index.html");
+            fs::write(&index, index_html(entries, &paths, dir))?;
+            println!("Wrote {}", index.display());
+            Ok(paths)
+        }
 
-                    return Ok((offset as i32, trial_size));
+        /// the sprites left to right, top to bottom, each with its name and author centred
+        /// underneath. cells are the size of the biggest sprite
+        fn sheet(
+            &self,
+            entries: &[Entry],
+            name_font: &FontRef,
+            author_font: &FontRef,
+        ) -> RgbaImage {
+            let scale = self.scale.factor();
+            let sprite_width = entries.iter().map(|e| e.image.width()).max().unwrap_or(0) * scale;
+            let sprite_height = entries.iter().map(|e| e.image.height()).max().unwrap_or(0) * scale;
+            // wide enough for a short name even when the sprites are tiny
+            let cell_width = sprite_width.max(96);
+            let has_authors = entries.iter().any(|e| !e.author.is_empty());
+            let label_height = NAME_SIZE.ceil() as u32
+                + 2
+                + if has_authors {
+                    AUTHOR_SIZE.ceil() as u32 + 2
+                } else {
+                    0
+                };
+            let cell_height = sprite_height + label_height;
+            let columns = self.columns.min(entries.len() as u32).max(1);
+            let rows = (entries.len() as u32).div_ceil(columns);
+            let mut out = RgbaImage::from_pixel(
+                columns * (cell_width + SPACING) + SPACING,
+                rows * (cell_height + SPACING) + SPACING,
+                BACKGROUND,
+            );
+            for (n, entry) in entries.iter().enumerate() {
+                let n = n as u32;
+                let x = SPACING + (n % columns) * (cell_width + SPACING);
+                let y = SPACING + (n / columns) * (cell_height + SPACING);
+                let sprite = upscale(&entry.image, self.scale);
+                let sprite_x = x + (cell_width - sprite.width()) / 2;
+                let sprite_y = y + sprite_height - sprite.height();
+                overlay(&mut out, &sprite, sprite_x as i64, sprite_y as i64);
+                let mut text_y = y + sprite_height + 1;
+                for (text, font, size, colour) in [
+                    (&entry.name, name_font, NAME_SIZE, NAME_COLOUR),
+                    (&entry.author, author_font, AUTHOR_SIZE, AUTHOR_COLOUR),
+                ] {
+                    if text.is_empty() {
+                        continue;
+                    }
+                    let text = fit(text, font, size, cell_width);
+                    let (w, _) = text_size(size, font, &text);
+                    draw_text_mut(
+                        &mut out,
+                        colour,
+                        (x + cell_width.saturating_sub(w) / 2) as i32,
+                        text_y as i32,
+                        size,
+                        font,
+                        &text,
+                    );
+                    text_y += size.ceil() as u32 + 2;
                 }
             }
-            Err(anyhow::anyhow!("Could not find a good size for this text"))
+            out
+        }
+    }
+
+    /// `text`, cut short with `...` if it'd be wider than `width`
+    fn fit(text: &str, font: &FontRef, size: f32, width: u32) -> String {
+        if text_size(size, font, text).0 <= width {
+            return text.to_string();
+        }
+        let mut chars: Vec<char> = text.chars().collect();
+        while !chars.is_empty() {
+            chars.pop();
+            let short = format!("{}...", chars.iter().collect::<String>().trim_end());
+            if text_size(size, font, &short).0 <= width {
+                return short;
+            }
+        }
+        String::new()
+    }
+
+    fn escape(text: &str) -> String {
+        text.replace('&', "&amp;")
+            .replace('<', "&lt;")
+            .replace('>', "&gt;")
+            .replace('"', "&quot;")
+    }
+
+    /// path from the gallery directory to a file in it or in one next to it
+    fn link(path: &Path, dir: &Path) -> String {
+        if let Ok(inside) = path.strip_prefix(dir) {
+            return inside.display().to_string();
+        }
+        let name = |p: Option<&Path>| {
+            p.and_then(Path::file_name)
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned()
         };
+        format!("../{}/{}", name(path.parent()), name(Some(path)))
+    }
 
-        let (w, s) = situate_text(bigger.width(), &self.name)?;
-        imageproc::drawing::draw_text_mut(
-            &mut bigger,
-            Rgba::from([222, 32, 32, 255]),
-            w,
-            h - s as i32 - 4,
-            s,
-            &font,
-            &self.name,
+    /// the sheets, then every sprite with a thumbnail of its card linking to the full size one
+    fn index_html(entries: &[Entry], sheets: &[PathBuf], dir: &Path) -> String {
+        let mut html = String::from(
+            "<!doctype html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>Sprites</title>\n\
+             <style>\nbody { background: #202020; color: #eee; font-family: sans-serif; }\n\
+             a { color: #eee; }\n.sprites { display: flex; flex-wrap: wrap; gap: 12px; }\n\
+             .sprite { width: 160px; text-align: center; }\n\
+             .sprite img { max-width: 160px; image-rendering: pixelated; }\n</style>\n\
+             </head>\n<body>\n",
         );
-
-        create_dir_all(big_dir)?;
-        bigger.save(embiggened_path)?;
-        Ok(())
+        let _ = writeln!(html, "<h1>{} sprites</h1>", entries.len());
+        if sheets.len() > 1 {
+            html.push_str("<p>");
+            for (n, sheet) in sheets.iter().enumerate() {
+                let _ = write!(
+                    html,
+                    "<a href=\"{}\">sheet {}</a> ",
+                    escape(&link(sheet, dir)),
+                    n + 1
+                );
+            }
+            html.push_str("</p>\n");
+        } else if let Some(sheet) = sheets.first() {
+            let _ = writeln!(
+                html,
+                "<p><a href=\"{}\">whole gallery</a></p>",
+                escape(&link(sheet, dir))
+            );
+        }
+        html.push_str("<div class=\"sprites\">\n");
+        for e in entries {
+            let card = escape(&link(&e.card, dir));
+            let _ = write!(
+                html,
+                "<div class=\"sprite\"><a href=\"{card}\"><img src=\"{card}\" loading=\"lazy\" \
+                 alt=\"{name}\"><br>{name}</a>",
+                name = escape(&e.name)
+            );
+            if !e.author.is_empty() {
+                let _ = write!(html, "<br><small>{}</small>", escape(&e.author));
+            }
+            html.push_str("</div>\n");
+        }
+        html.push_str("</div>\n</body>\n</html>\n");
+        html
     }
 }
 
 mod sprites {
     use std::{
-        fs::{create_dir_all, read_to_string, remove_file},
+        fs::{create_dir_all, read_to_string, remove_file, rename, write},
         path::{Path, PathBuf},
+        time::{Duration, SystemTime, UNIX_EPOCH},
     };
 
-    use reqwest::blocking::Client;
+    use image_misc::fetch::Fetcher;
 
     use crate::{Args, Sprite};
 
-    pub fn get_sprites_metadata(c: &Client, args: &Args) -> anyhow::Result<Vec<Sprite>> {
-        let raw = if let Some(r) = get_sprites_from_filesystem(args)? {
-            println!("Got sprites from filesystem cache");
-            r
-        } else if let Some(r) = get_sprites_from_api(c)? {
-            println!("Got sprites from API");
-            r
-        } else {
-            return Err(anyhow::anyhow!("Could not find sprite metadata"));
-        };
-        let cached_path = Path::new("./sprites/sprites.json");
-        let parsed = match serde_json::from_str::<Vec<Sprite>>(&raw) {
-            Ok(parsed) => {
-                std::fs::write(cached_path, &raw).ok();
-                parsed
-            }
+    /// everything fetched from alttpr.com is cached in here
+    pub const CACHE_DIR: &str = "./sprites";
+
+    /// stored next to the cached sprite list so we know when to check for a new one
+    #[derive(serde::Serialize, serde::Deserialize, Debug)]
+    struct CacheMeta {
+        /// unix seconds
+        fetched_at: u64,
+        etag: Option<String>,
+    }
+
+    impl CacheMeta {
+        fn age(&self) -> Duration {
+            Duration::from_secs(now().saturating_sub(self.fetched_at))
+        }
+    }
+
+    fn sprites_path() -> PathBuf {
+        Path::new(CACHE_DIR).join("sprites.json")
+    }
+
+    fn meta_path() -> PathBuf {
+        Path::new(CACHE_DIR).join("sprites.meta.json")
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_secs())
+            .unwrap_or_default()
+    }
+
+    /// the cached sprite list, if it's there and parses. a broken cache is deleted
+    fn read_cache() -> anyhow::Result<Option<(String, Vec<Sprite>)>> {
+        let p = sprites_path();
+        if !p.exists() {
+            return Ok(None);
+        }
+        // N.B. i know this is inefficient i just dont care that much
+        let raw = read_to_string(&p)?;
+        match serde_json::from_str::<Vec<Sprite>>(&raw) {
+            Ok(parsed) => Ok(Some((raw, parsed))),
             Err(e) => {
-                remove_file(cached_path).ok();
-                return Err(anyhow::anyhow!("Error parsing sprites json: {e}"));
+                println!("Cached sprite list is broken, ignoring it: {e}");
+                remove_file(&p).ok();
+                remove_file(meta_path()).ok();
+                Ok(None)
             }
-        };
+        }
+    }
+
+    fn read_meta() -> Option<CacheMeta> {
+        serde_json::from_str(&read_to_string(meta_path()).ok()?).ok()
+    }
 
-        Ok(parsed)
+    fn write_cache(raw: &str, meta: &CacheMeta) -> anyhow::Result<()> {
+        create_dir_all(CACHE_DIR)?;
+        // write then rename so a crash never leaves half a file behind
+        let tmp = sprites_path().with_extension("json.tmp");
+        write(&tmp, raw)?;
+        rename(&tmp, sprites_path())?;
+        write(meta_path(), serde_json::to_string_pretty(meta)?)?;
+        Ok(())
     }
 
-    fn get_sprites_from_filesystem(args: &Args) -> anyhow::Result<Option<String>> {
-        let p = PathBuf::from(format!("./sprites_{}", args.username));
-        create_dir_all(&p)?;
-        let s = p.join("sprites.json");
-        // TODO: cache bust
-        if s.exists() {
-            // N.B. i know this is inefficient i just dont care that much
-            Ok(Some(read_to_string(s)?))
+    /// the cached list is used as is while it's younger than `--cache-ttl-hours`. after that the
+    /// api is asked again with the cached etag, and a 304 just resets the clock. `--refresh`
+    /// skips the cache entirely and `--offline` never leaves it
+    pub fn get_sprites_metadata(c: &dyn Fetcher, args: &Args) -> anyhow::Result<Vec<Sprite>> {
+        let cached = read_cache()?;
+        let meta = read_meta();
+
+        if args.offline {
+            return match cached {
+                Some((_, parsed)) => {
+                    println!("Got sprites from filesystem cache (offline)");
+                    Ok(parsed)
+                }
+                None => Err(anyhow::anyhow!(
+                    "--offline was given but there's no cached sprite list at {}. run once without --offline to fill the cache",
+                    sprites_path().display()
+                )),
+            };
+        }
+
+        let ttl = Duration::from_secs(args.cache_ttl_hours * 60 * 60);
+        let fresh = meta.as_ref().is_some_and(|m| m.age() < ttl);
+        if fresh && !args.refresh {
+            if let Some((_, parsed)) = cached {
+                println!("Got sprites from filesystem cache");
+                return Ok(parsed);
+            }
+        }
+
+        let etag = if args.refresh {
+            None
         } else {
-            Ok(None)
+            cached.as_ref().and(meta.and_then(|m| m.etag))
+        };
+        match get_sprites_from_api(c, &args.sprites_url, etag.as_deref()) {
+            Ok(ApiResponse::NotModified) => {
+                println!("Sprite list hasn't changed, using filesystem cache");
+                let (raw, parsed) = cached.ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "{} said the sprite list wasn't modified, but there's no cached one",
+                        args.sprites_url
+                    )
+                })?;
+                write_cache(
+                    &raw,
+                    &CacheMeta {
+                        fetched_at: now(),
+                        etag,
+                    },
+                )?;
+                Ok(parsed)
+            }
+            Ok(ApiResponse::Body { raw, etag }) => {
+                println!("Got sprites from API");
+                let parsed = serde_json::from_str::<Vec<Sprite>>(&raw)
+                    .map_err(|e| anyhow::anyhow!("Error parsing sprites json: {e}"))?;
+                write_cache(
+                    &raw,
+                    &CacheMeta {
+                        fetched_at: now(),
+                        etag,
+                    },
+                )?;
+                Ok(parsed)
+            }
+            Err(e) => match cached {
+                Some((_, parsed)) => {
+                    println!("Error fetching sprites ({e}), using stale filesystem cache");
+                    Ok(parsed)
+                }
+                None => Err(anyhow::anyhow!("Could not fetch sprite metadata: {e}")),
+            },
         }
     }
 
-    fn get_sprites_from_api(c: &Client, args: &Args) -> anyhow::Result<Option<String>> {
-        let req = c.get("https://alttpr.com/sprites").build()?;
-        let resp = c.execute(req)?;
+    enum ApiResponse {
+        NotModified,
+        Body { raw: String, etag: Option<String> },
+    }
 
-        let body = std::io::read_to_string(resp)?;
-        Ok(Some(body))
+    fn get_sprites_from_api(
+        c: &dyn Fetcher,
+        url: &str,
+        etag: Option<&str>,
+    ) -> anyhow::Result<ApiResponse> {
+        let headers: Vec<(&str, &str)> = etag.map(|e| ("if-none-match", e)).into_iter().collect();
+        let resp = c.fetch(url, &headers)?;
+        if resp.status == 304 {
+            return Ok(ApiResponse::NotModified);
+        }
+        let resp = resp.error_for_status(url)?;
+        let etag = resp.header("etag").map(|v| v.to_string());
+        let raw = resp.text()?;
+        Ok(ApiResponse::Body { raw, etag })
     }
 }
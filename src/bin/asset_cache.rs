@@ -0,0 +1,70 @@
+//! looking after the shared asset cache. uses the same `ASSET_CACHE_DIR` and
+//! `ASSET_CACHE_MAX_MB` as the binaries that fill it
+
+use clap::{Parser, Subcommand};
+use image_misc::asset_cache::AssetCache;
+
+#[derive(Parser, Debug)]
+struct Args {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// every cached url with its size and when it was fetched
+    List,
+    /// re-hash everything, dropping damaged entries and stray files
+    Verify,
+    /// forget one url, or everything if none is given
+    Clear { url: Option<String> },
+    /// drop least recently used entries until the cache is at most this many megabytes
+    Prune { max_mb: u64 },
+}
+
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let cache = AssetCache::from_env()?;
+    match args.command {
+        Command::List => {
+            let entries = cache.entries()?;
+            for (url, e) in &entries {
+                println!("{} {:>8} {} {url}", e.hash, e.size, e.fetched_at);
+            }
+            println!(
+                "{} entries, {} KiB in {}",
+                entries.len(),
+                cache.total_size()? / 1024,
+                cache.dir().display()
+            );
+        }
+        Command::Verify => {
+            let report = cache.verify()?;
+            for url in &report.corrupt {
+                println!("Damaged, removed: {url}");
+            }
+            println!(
+                "{} ok, {} damaged, {} stray files deleted",
+                report.ok,
+                report.corrupt.len(),
+                report.orphans
+            );
+        }
+        Command::Clear { url: Some(url) } => {
+            if cache.remove(&url)? {
+                println!("Removed {url}");
+            } else {
+                println!("{url} wasn't cached");
+            }
+        }
+        Command::Clear { url: None } => {
+            cache.clear()?;
+            println!("Cleared {}", cache.dir().display());
+        }
+        Command::Prune { max_mb } => {
+            let evicted = cache.prune(max_mb * 1024 * 1024)?;
+            println!("Evicted {evicted} entries");
+        }
+    }
+    Ok(())
+}
@@ -1,35 +1,35 @@
-use std::{
-    collections::HashMap,
-    fs::{self, read_to_string, File},
-    io::{BufReader, BufWriter, Cursor, Read, Write},
-    marker::PhantomData,
-    path::Path,
-};
+use std::{collections::HashMap, fs, io::Cursor, sync::Arc};
 
 use ab_glyph::FontRef;
-use anyhow::{anyhow, Result};
-use bytes::Buf;
-use image::{
-    imageops::{crop, resize},
-    DynamicImage, GenericImage, GenericImageView, ImageBuffer, Pixel, Rgb, Rgba,
-};
+use anyhow::Result;
+use image::{DynamicImage, GenericImage, ImageBuffer, Pixel, Rgba};
+use image_misc::asset_cache::{validate_image, AssetCache};
+use image_misc::fetch::Fetcher;
 use itertools::Itertools;
-use regex::Regex;
 use speedrun_api::{
-    api::{games::GameId, variables::ValueId, Client, Query, Root},
-    types::{Game, Run, Times, User, Variable},
-    SpeedrunApiClient,
+    api::{games::GameId, variables::ValueId, Root},
+    types::{Game, Run, Times, Variable},
 };
 
+const SPEEDRUN_API: &str = "https://www.speedrun.com/api/v1";
+
+/// a GET against the speedrun.com api, parsed out of its `{"data": ...}` wrapper
+fn get_api<T: serde::de::DeserializeOwned>(fetcher: &dyn Fetcher, path: &str) -> Result<T> {
+    let url = format!("{SPEEDRUN_API}{path}");
+    let resp = fetcher.get(&url)?.error_for_status(&url)?;
+    let root: Root<T> = serde_json::from_slice(&resp.body)?;
+    Ok(root.data)
+}
+
 struct CategoryNameFigureOuter<'a> {
-    client: SpeedrunApiClient,
+    fetcher: Arc<dyn Fetcher>,
     variables: HashMap<String, HashMap<String, Variable<'a>>>,
 }
 
 impl<'a> CategoryNameFigureOuter<'a> {
-    fn new(client: SpeedrunApiClient) -> Self {
+    fn new(fetcher: Arc<dyn Fetcher>) -> Self {
         Self {
-            client,
+            fetcher,
             variables: Default::default(),
         }
     }
@@ -39,10 +39,8 @@ impl<'a> CategoryNameFigureOuter<'a> {
         if let Some(vars) = self.variables.get(&gid) {
             return Ok(vars.clone());
         }
-        let req = speedrun_api::api::games::GameVariables::builder()
-            .id(game_id.to_string())
-            .build()?;
-        let resp: Vec<Variable> = req.query(&self.client)?;
+        let resp: Vec<Variable> =
+            get_api(self.fetcher.as_ref(), &format!("/games/{gid}/variables"))?;
         let vars: HashMap<String, Variable> =
             resp.into_iter().map(|v| (v.id.to_string(), v)).collect();
         let out = vars.clone();
@@ -89,57 +87,44 @@ impl<'a> CategoryNameFigureOuter<'a> {
 }
 
 fn main() -> Result<()> {
-    let blob = read_to_string("bydey_categories/pbs.json")?;
-    let parsed: Root<Vec<PersonalBest<'_>>> = serde_json::from_str(&blob)?;
-
-    let client = SpeedrunApiClient::new()?;
-    let reqw_client = reqwest::blocking::Client::new();
-    let mut cnfo = CategoryNameFigureOuter::new(client.clone());
-    let bydey = get_user(&client)?;
+    let fetcher = image_misc::fetch::from_env()?;
+    let cache = AssetCache::from_env()?;
+    let mut cnfo = CategoryNameFigureOuter::new(fetcher.clone());
+    let bydey = get_user(fetcher.as_ref())?;
     fs::create_dir_all("bydey_categories")?;
-    fs::create_dir_all("bydey_categories/covers")?;
     fs::create_dir_all("bydey_categories/pbs")?;
 
     struct PB<'a> {
         game: Game<'a>,
         cat: String,
         // time: String,
+        #[allow(dead_code)]
         place: u32,
     }
 
     impl<'a> PB<'a> {
-        fn from(mut game: Game<'a>, cat: String, _time: Times, place: u32) -> Self {
+        fn from(game: Game<'a>, cat: String, _time: Times, place: u32) -> Self {
             Self { game, cat, place }
         }
 
-        fn get_cover(&self, client: &reqwest::blocking::Client) -> Result<DynamicImage> {
-            let pathname = format!("bydey_categories/covers/{}.png", self.game.id.to_string());
-            let p = Path::new(&pathname);
-            if p.exists() {
-                let f = File::open(p)?;
-                let br = BufReader::new(f);
-                let i = image::io::Reader::new(br).with_guessed_format()?.decode()?;
-                return Ok(i);
-            }
+        fn get_cover(&self, fetcher: &dyn Fetcher, cache: &AssetCache) -> Result<DynamicImage> {
             let url = self
                 .game
                 .assets
                 .cover_medium
                 .uri
                 .as_ref()
-                .expect(&format!(
-                    "Missing cover URL for game {}",
-                    self.game.names.international
-                ))
+                .unwrap_or_else(|| {
+                    panic!(
+                        "Missing cover URL for game {}",
+                        self.game.names.international
+                    )
+                })
                 .to_string();
-            let mut resp = client.get(url).send()?;
-            let mut body_u8s: Vec<u8> = Vec::new();
-            resp.copy_to(&mut body_u8s)?;
-            let i = image::io::Reader::new(Cursor::new(body_u8s))
+            let data = cache.get_or_fetch(fetcher, &url, false, validate_image)?;
+            let i = image::io::Reader::new(Cursor::new(data))
                 .with_guessed_format()?
                 .decode()?;
-
-            i.save(p)?;
             Ok(i)
         }
     }
@@ -159,7 +144,7 @@ fn main() -> Result<()> {
     let font = ab_glyph::FontRef::try_from_slice(include_bytes!("../../fonts/ARIALBD.TTF"))?;
 
     for pb in pbs {
-        let c = pb.get_cover(&reqw_client)?;
+        let c = pb.get_cover(fetcher.as_ref(), &cache)?;
 
         let padded = {
             if c.width() > c.height() {
@@ -198,8 +183,7 @@ fn main() -> Result<()> {
 
         let filename = format!("{}_{}.png", pb.game.names.international, pb.cat)
             .to_lowercase()
-            .replace(' ', "_")
-            .replace(':', "_")
+            .replace([' ', ':'], "_")
             .replace('\'', "")
             .replace('/', "_");
         let filepath = format!("bydey_categories/pbs/{filename}");
@@ -222,7 +206,7 @@ fn write_potentially_long_text(
         let mut chunk = String::new();
         for word in words {
             chunk.push_str(word);
-            chunk.push_str(" ");
+            chunk.push(' ');
             if chunk.len() >= 12 {
                 to_write.push(chunk.clone());
                 chunk.clear();
@@ -232,12 +216,12 @@ fn write_potentially_long_text(
             to_write.push(chunk);
         }
         for thing in to_write {
-            write_text(image, 0, y_start, &font, &thing);
+            write_text(image, 0, y_start, font, &thing);
             y_start += 48;
         }
         y_start
     } else {
-        write_text(image, 0, y_start, &font, text);
+        write_text(image, 0, y_start, font, text);
         y_start
     }
 }
@@ -262,6 +246,7 @@ fn write_text(
 
 #[derive(serde::Deserialize, Debug)]
 struct Category {
+    #[allow(dead_code)]
     pub id: String,
     pub name: String,
 }
@@ -274,12 +259,6 @@ struct PersonalBest<'a> {
     category: Root<Category>,
 }
 
-fn get_user(c: &SpeedrunApiClient) -> Result<Vec<PersonalBest>> {
-    let req = speedrun_api::api::users::UserPersonalBests::builder()
-        .id("Bydey")
-        .embed(speedrun_api::api::runs::RunEmbeds::Game)
-        .embed(speedrun_api::api::runs::RunEmbeds::Category)
-        .build()?;
-
-    Ok(req.query(c)?)
+fn get_user(fetcher: &dyn Fetcher) -> Result<Vec<PersonalBest<'static>>> {
+    get_api(fetcher, "/users/Bydey/personal-bests?embed=game,category")
 }
@@ -26,7 +26,7 @@ fn main() -> anyhow::Result<()> {
         ("Season 6", "NMG League"),
     ];
 
-    let backgrounds = vec![[128 + 32, 64 + 32, 128 + 64], [128 + 64, 64 + 32, 128 + 32]];
+    let backgrounds = [[128 + 32, 64 + 32, 128 + 64], [128 + 64, 64 + 32, 128 + 32]];
     let mut bg_iter = backgrounds.iter().cycle();
 
     let font = ab_glyph::FontRef::try_from_slice(include_bytes!("../../fonts/ARIALBD.TTF"))?;
@@ -49,7 +49,7 @@ fn draw_image(
     let size = 512;
     let mut i = image::RgbImage::new(size, size);
     for p in i.pixels_mut() {
-        p.0 = bg.clone();
+        p.0 = *bg;
     }
     let when_scale = 112.0;
     let (when_x, _) = text_size(when_scale, font, when);
@@ -0,0 +1,95 @@
+use crate::env_var_opt;
+use crate::events::frame_difference;
+use image::DynamicImage;
+
+pub struct ClipConfig {
+    /// fraction of pixels that have to change between frames for the frame to count as motion
+    pub motion_threshold: f64,
+    /// how much a channel has to change by for a pixel to count as changed
+    pub pixel_tolerance: u8,
+    /// motion separated by fewer static frames than this is kept in the same clip
+    pub max_static_gap: usize,
+    /// clips shorter than this are dropped
+    pub min_clip_frames: usize,
+}
+
+impl ClipConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let motion_threshold = env_var_opt("MOTION_THRESHOLD")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(0.002);
+        let pixel_tolerance = env_var_opt("PIXEL_TOLERANCE")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(16);
+        let max_static_gap = env_var_opt("MAX_STATIC_GAP")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(30);
+        let min_clip_frames = env_var_opt("MIN_CLIP_FRAMES")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(10);
+        Ok(Self {
+            motion_threshold,
+            pixel_tolerance,
+            max_static_gap,
+            min_clip_frames,
+        })
+    }
+}
+
+/// a suggested clip. `start` is an index into the sorted frame list, i.e. `SKIP_IMAGES`
+#[derive(Debug, Clone, Copy)]
+pub struct Clip {
+    pub start: usize,
+    pub len: usize,
+}
+
+/// how much changed going into each frame. the first frame has nothing to compare to, so it
+/// always scores 0
+pub fn motion_scores<I: Iterator<Item = DynamicImage>>(images: I, pixel_tolerance: u8) -> Vec<f64> {
+    let mut scores = vec![];
+    let mut previous: Option<DynamicImage> = None;
+    for image in images {
+        scores.push(match &previous {
+            Some(p) => frame_difference(p, &image, pixel_tolerance),
+            None => 0.0,
+        });
+        previous = Some(image);
+    }
+    scores
+}
+
+/// groups frames with motion into clips, with the static frames before and after trimmed off.
+/// each clip keeps the frame just before its first change, so it starts from a still image
+pub fn suggest_clips(scores: &[f64], config: &ClipConfig) -> Vec<Clip> {
+    let mut clips: Vec<Clip> = vec![];
+    // (first moving frame, last moving frame)
+    let mut current: Option<(usize, usize)> = None;
+    let finish = |first: usize, last: usize, clips: &mut Vec<Clip>| {
+        let start = first.saturating_sub(1);
+        let len = last - start + 1;
+        if len >= config.min_clip_frames {
+            clips.push(Clip { start, len });
+        }
+    };
+    for (i, score) in scores.iter().enumerate() {
+        if *score <= config.motion_threshold {
+            continue;
+        }
+        current = match current {
+            Some((first, last)) if i - last <= config.max_static_gap => Some((first, i)),
+            Some((first, last)) => {
+                finish(first, last, &mut clips);
+                Some((i, i))
+            }
+            None => Some((i, i)),
+        };
+    }
+    if let Some((first, last)) = current {
+        finish(first, last, &mut clips);
+    }
+    clips
+}
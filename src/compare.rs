@@ -0,0 +1,212 @@
+use crate::align::find_best_offset;
+use crate::{env_var, env_var_opt, get_images_from, Cropper, ImageSelectionConfig};
+use ab_glyph::FontRef;
+use anyhow::anyhow;
+use image::{DynamicImage, GenericImage, Rgba, RgbaImage};
+use imageproc::drawing::draw_text_mut;
+
+/// one capture directory taking part in a comparison
+pub struct ComparisonSource {
+    pub dir: String,
+    /// extra frames to skip in this source (on top of `SKIP_IMAGES`), to line the sources up
+    pub offset: usize,
+    pub label: Option<String>,
+}
+
+impl ComparisonSource {
+    /// `IMAGE_DIRS` is a comma separated list of directories under `images/`.
+    /// `FRAME_OFFSETS` and `LABELS` are optional comma separated lists in the same order.
+    /// `FRAME_OFFSETS=auto` leaves the offsets at 0 for `align_sources` to fill in
+    pub fn all_from_env() -> anyhow::Result<Vec<Self>> {
+        let dirs: Vec<String> = env_var("IMAGE_DIRS")
+            .split(',')
+            .map(|d| d.trim().to_string())
+            .filter(|d| !d.is_empty())
+            .collect();
+        if dirs.len() < 2 {
+            return Err(anyhow!("IMAGE_DIRS needs at least two directories"));
+        }
+        let offsets: Vec<usize> = match env_var_opt("FRAME_OFFSETS") {
+            Some(o) if o == "auto" => vec![],
+            Some(o) => o
+                .split(',')
+                .map(|o| o.trim().parse())
+                .collect::<Result<_, _>>()?,
+            None => vec![],
+        };
+        let labels: Vec<String> = match env_var_opt("LABELS") {
+            Some(l) => l.split(',').map(|l| l.trim().to_string()).collect(),
+            None => vec![],
+        };
+        Ok(dirs
+            .into_iter()
+            .enumerate()
+            .map(|(n, dir)| Self {
+                dir,
+                offset: offsets.get(n).copied().unwrap_or(0),
+                label: labels.get(n).filter(|l| !l.is_empty()).cloned(),
+            })
+            .collect())
+    }
+}
+
+/// sets every source's offset so it lines up with the first source, by comparing the first
+/// `window + max_offset` frames of each. see `find_best_offset`.
+/// this looks at every captured frame even with `SKIP_ALTERNATING`, so the offsets count
+/// captured frames like `SKIP_IMAGES` and `FRAME_OFFSETS` do, and can be odd
+pub fn align_sources(
+    sources: &mut [ComparisonSource],
+    isc: &ImageSelectionConfig,
+    max_offset: usize,
+    window: usize,
+    crop: Option<&Cropper>,
+) -> anyhow::Result<()> {
+    let load = |dir: &str| -> anyhow::Result<Vec<DynamicImage>> {
+        Ok(get_images_from(
+            dir,
+            ImageSelectionConfig {
+                take: window + max_offset,
+                skip_alternating: false,
+                ..*isc
+            },
+        )?
+        .map(|(i, _)| i)
+        .collect())
+    };
+    let Some(first) = sources.first() else {
+        return Ok(());
+    };
+    let reference = load(&first.dir)?;
+    // offset of each source relative to the first one, which can be negative
+    let mut offsets = vec![0i64];
+    for source in sources.iter().skip(1) {
+        let frames = load(&source.dir)?;
+        let alignment = find_best_offset(&reference, &frames, max_offset, window, crop)
+            .ok_or_else(|| anyhow!("Not enough frames in {} to align it", source.dir))?;
+        println!(
+            "{} is offset {} frames from {} (difference {:.4})",
+            source.dir, alignment.offset, first.dir, alignment.score
+        );
+        offsets.push(alignment.offset);
+    }
+    let base = offsets.iter().copied().min().unwrap_or(0).min(0);
+    for (source, offset) in sources.iter_mut().zip(offsets) {
+        source.offset = (offset - base) as usize;
+    }
+    Ok(())
+}
+
+pub enum Layout {
+    SideBySide,
+    Stacked,
+    Grid { columns: u32 },
+}
+
+impl Layout {
+    pub fn from_env() -> anyhow::Result<Self> {
+        match env_var_opt("LAYOUT").as_deref() {
+            None | Some("side_by_side") => Ok(Layout::SideBySide),
+            Some("stacked") => Ok(Layout::Stacked),
+            Some("grid") => Ok(Layout::Grid {
+                columns: env_var("GRID_COLUMNS").parse()?,
+            }),
+            Some(other) => Err(anyhow!("Unknown LAYOUT {other}")),
+        }
+    }
+
+    /// (columns, rows) needed for `count` cells
+    fn dimensions(&self, count: u32) -> (u32, u32) {
+        match self {
+            Layout::SideBySide => (count, 1),
+            Layout::Stacked => (1, count),
+            Layout::Grid { columns } => {
+                let columns = (*columns).max(1);
+                (columns, count.div_ceil(columns))
+            }
+        }
+    }
+}
+
+/// reads every source in step, applying each source's offset on top of `isc`.
+/// sources that run out early keep showing their last frame until every source is done
+pub fn get_synced_images(
+    sources: &[ComparisonSource],
+    isc: &ImageSelectionConfig,
+) -> anyhow::Result<impl Iterator<Item = Vec<DynamicImage>>> {
+    let mut iters = sources
+        .iter()
+        .map(|s| {
+            get_images_from(
+                &s.dir,
+                ImageSelectionConfig {
+                    skip: isc.skip + s.offset,
+                    take: isc.take,
+                    skip_alternating: isc.skip_alternating,
+                },
+            )
+            .map(|i| i.map(|(i, _)| i))
+        })
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    let mut last: Vec<Option<DynamicImage>> = vec![None; sources.len()];
+    Ok(std::iter::from_fn(move || {
+        let mut any_new = false;
+        for (it, last) in iters.iter_mut().zip(last.iter_mut()) {
+            if let Some(i) = it.next() {
+                *last = Some(i);
+                any_new = true;
+            }
+        }
+        if !any_new || last.iter().any(|l| l.is_none()) {
+            return None;
+        }
+        Some(last.iter().flatten().cloned().collect())
+    }))
+}
+
+/// lays the frames out in cells the size of the biggest frame, with `spacing` pixels of black
+/// between them. labels are drawn in the top left of each cell
+pub fn composite(
+    frames: &[DynamicImage],
+    labels: &[Option<&str>],
+    layout: &Layout,
+    spacing: u32,
+    font: &FontRef,
+    label_size: f32,
+) -> anyhow::Result<DynamicImage> {
+    let cell_width = frames.iter().map(|f| f.width()).max().unwrap_or(0);
+    let cell_height = frames.iter().map(|f| f.height()).max().unwrap_or(0);
+    let (columns, rows) = layout.dimensions(frames.len() as u32);
+    let mut out = RgbaImage::from_pixel(
+        columns * cell_width + columns.saturating_sub(1) * spacing,
+        rows * cell_height + rows.saturating_sub(1) * spacing,
+        Rgba([0, 0, 0, 255]),
+    );
+    for (n, frame) in frames.iter().enumerate() {
+        let n = n as u32;
+        let x = (n % columns) * (cell_width + spacing);
+        let y = (n / columns) * (cell_height + spacing);
+        out.copy_from(&frame.to_rgba8(), x, y)?;
+        if let Some(Some(label)) = labels.get(n as usize) {
+            // a drop shadow so the label is readable on any background
+            draw_text_mut(
+                &mut out,
+                Rgba([0, 0, 0, 255]),
+                x as i32 + 3,
+                y as i32 + 3,
+                label_size,
+                font,
+                label,
+            );
+            draw_text_mut(
+                &mut out,
+                Rgba([255, 255, 255, 255]),
+                x as i32 + 2,
+                y as i32 + 2,
+                label_size,
+                font,
+                label,
+            );
+        }
+    }
+    Ok(DynamicImage::ImageRgba8(out))
+}
@@ -0,0 +1,87 @@
+use crate::annotate::colour_from_env;
+use crate::env_var_opt;
+use ab_glyph::FontRef;
+use image::{DynamicImage, GenericImage, Rgba, RgbaImage};
+use imageproc::drawing::{draw_text_mut, text_size};
+
+pub struct SheetLayout {
+    pub columns: u32,
+    /// gap between cells and around the edge, in pixels
+    pub spacing: u32,
+    pub background: Rgba<u8>,
+    /// 0 turns the frame number labels off
+    pub label_size: f32,
+    pub label_colour: Rgba<u8>,
+}
+
+impl SheetLayout {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let columns = env_var_opt("SHEET_COLUMNS")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(8u32);
+        let spacing = env_var_opt("SHEET_SPACING")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(4);
+        let label_size = env_var_opt("SHEET_LABEL_SIZE")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(12.0);
+        Ok(Self {
+            columns: columns.max(1),
+            spacing,
+            background: colour_from_env("SHEET_BACKGROUND")?.unwrap_or(Rgba([32, 32, 32, 255])),
+            label_size,
+            label_colour: colour_from_env("SHEET_LABEL_COLOUR")?
+                .unwrap_or(Rgba([255, 255, 255, 255])),
+        })
+    }
+
+    fn label_height(&self) -> u32 {
+        if self.label_size > 0.0 {
+            self.label_size.ceil() as u32 + 2
+        } else {
+            0
+        }
+    }
+}
+
+/// lays `frames` out left to right, top to bottom, each with its frame number (if it has one)
+/// centred underneath. cells are the size of the biggest frame
+pub fn contact_sheet(
+    frames: &[(DynamicImage, Option<u32>)],
+    layout: &SheetLayout,
+    font: &FontRef,
+) -> anyhow::Result<RgbaImage> {
+    let cell_width = frames.iter().map(|(f, _)| f.width()).max().unwrap_or(0);
+    let cell_height =
+        frames.iter().map(|(f, _)| f.height()).max().unwrap_or(0) + layout.label_height();
+    let columns = layout.columns.min(frames.len() as u32).max(1);
+    let rows = (frames.len() as u32).div_ceil(columns);
+    let mut out = RgbaImage::from_pixel(
+        columns * (cell_width + layout.spacing) + layout.spacing,
+        rows * (cell_height + layout.spacing) + layout.spacing,
+        layout.background,
+    );
+    for (n, (frame, number)) in frames.iter().enumerate() {
+        let n = n as u32;
+        let x = layout.spacing + (n % columns) * (cell_width + layout.spacing);
+        let y = layout.spacing + (n / columns) * (cell_height + layout.spacing);
+        out.copy_from(&frame.to_rgba8(), x + (cell_width - frame.width()) / 2, y)?;
+        if let (Some(number), true) = (number, layout.label_size > 0.0) {
+            let text = number.to_string();
+            let (w, _) = text_size(layout.label_size, font, &text);
+            draw_text_mut(
+                &mut out,
+                layout.label_colour,
+                (x + cell_width.saturating_sub(w) / 2) as i32,
+                (y + frame.height() + 1) as i32,
+                layout.label_size,
+                font,
+                &text,
+            );
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,106 @@
+use crate::env_var_opt;
+use image::{DynamicImage, RgbaImage};
+use imageproc::filter::gaussian_blur_f32;
+
+/// a fake crt look for upscaled frames: dark gaps between scanlines, a vertical rgb aperture
+/// grille and a bit of glow around bright areas. all of it is plain per-pixel maths, so the same
+/// frame always comes out the same
+pub struct CrtFilter {
+    /// how dark the gap between scanlines gets, 0 (off) to 1 (black)
+    pub scanline_strength: f32,
+    /// output rows per source row, i.e. the integer upscale factor
+    pub scanline_period: u32,
+    /// how much of the blurred image is added back on top, 0 for no bloom
+    pub bloom: f32,
+    /// blur radius (sigma) for the bloom, in output pixels
+    pub bloom_radius: f32,
+    /// only pixels with a luma (0 to 255) above this glow, so dark areas stay dark
+    pub bloom_threshold: f32,
+    /// how much the other two channels are dimmed on each grille stripe, 0 (off) to 1
+    pub mask_strength: f32,
+}
+
+impl CrtFilter {
+    /// only on with `CRT=1`. `default_period` should be the upscale factor the frames went
+    /// through, and can be overridden with `CRT_SCANLINE_PERIOD`
+    pub fn from_env(default_period: u32) -> anyhow::Result<Option<Self>> {
+        if env_var_opt("CRT").as_deref() != Some("1") {
+            return Ok(None);
+        }
+        let get = |k: &str, default: f32| -> anyhow::Result<f32> {
+            Ok(env_var_opt(k)
+                .map(|v| v.parse())
+                .transpose()?
+                .unwrap_or(default))
+        };
+        let scanline_period = env_var_opt("CRT_SCANLINE_PERIOD")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(default_period);
+        Ok(Some(Self {
+            scanline_strength: get("CRT_SCANLINES", 0.4)?,
+            scanline_period: scanline_period.max(1),
+            bloom: get("CRT_BLOOM", 0.15)?,
+            bloom_radius: get("CRT_BLOOM_RADIUS", default_period as f32)?,
+            bloom_threshold: get("CRT_BLOOM_THRESHOLD", 160.0)?,
+            mask_strength: get("CRT_MASK", 0.2)?,
+        }))
+    }
+
+    /// brightness multiplier for output row `y`: full in the middle of each scanline, darkest at
+    /// the edges
+    fn scanline(&self, y: u32) -> f32 {
+        if self.scanline_period < 2 {
+            return 1.0;
+        }
+        let phase = (y % self.scanline_period) as f32 + 0.5;
+        let from_centre = (phase / self.scanline_period as f32 - 0.5).abs() * 2.0;
+        1.0 - self.scanline_strength * from_centre * from_centre
+    }
+
+    /// brightness multiplier for `channel` in output column `x`
+    fn mask(&self, x: u32, channel: usize) -> f32 {
+        if x as usize % 3 == channel {
+            1.0
+        } else {
+            1.0 - self.mask_strength
+        }
+    }
+
+    /// `base` with everything at or below `bloom_threshold` blacked out, ready to be blurred
+    fn bright_parts(&self, base: &RgbaImage) -> RgbaImage {
+        let mut bright = base.clone();
+        for px in bright.pixels_mut() {
+            let [r, g, b, _] = px.0.map(|c| c as f32);
+            if 0.299 * r + 0.587 * g + 0.114 * b <= self.bloom_threshold {
+                px.0[..3].fill(0);
+            }
+        }
+        bright
+    }
+
+    pub fn apply(&self, image: &DynamicImage) -> DynamicImage {
+        let base = image.to_rgba8();
+        let glow = if self.bloom > 0.0 && self.bloom_radius > 0.0 {
+            Some(gaussian_blur_f32(
+                &self.bright_parts(&base),
+                self.bloom_radius,
+            ))
+        } else {
+            None
+        };
+        let out = RgbaImage::from_fn(base.width(), base.height(), |x, y| {
+            let scanline = self.scanline(y);
+            let mut out = *base.get_pixel(x, y);
+            for (c, channel) in out.0.iter_mut().take(3).enumerate() {
+                let mut v = *channel as f32 * scanline * self.mask(x, c);
+                if let Some(glow) = &glow {
+                    v += glow.get_pixel(x, y).0[c] as f32 * self.bloom;
+                }
+                *channel = v.round().clamp(0.0, 255.0) as u8;
+            }
+            out
+        });
+        DynamicImage::ImageRgba8(out)
+    }
+}
@@ -0,0 +1,521 @@
+//! fetching lots of files at once. a bounded number of requests run at a time, and anything
+//! that fails in a way that might not happen again (5xx, 429, timeouts, dropped connections) is
+//! retried with exponential backoff. everything lands in the asset cache
+
+use crate::asset_cache::{AssetCache, Validate};
+use crate::fetch::Fetcher;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::Semaphore;
+use tokio::task::JoinSet;
+
+#[derive(Debug, Clone)]
+pub struct Download {
+    pub url: String,
+    /// what it's cached under. normally the url
+    pub key: String,
+    /// run on the body before it's cached. a body that fails is retried like a dropped connection
+    pub validate: Validate,
+}
+
+impl Download {
+    pub fn new(url: &str, validate: Validate) -> Self {
+        Self {
+            url: url.to_string(),
+            key: url.to_string(),
+            validate,
+        }
+    }
+
+    pub fn with_key(mut self, key: String) -> Self {
+        self.key = key;
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadConfig {
+    /// most requests in flight at once
+    pub concurrency: usize,
+    /// retries after the first attempt
+    pub max_retries: u32,
+    /// wait before the first retry, doubled each time after
+    pub base_delay: Duration,
+    /// longest a server's Retry-After is waited for, so one header can't stall the whole run
+    pub max_retry_after: Duration,
+}
+
+impl Default for DownloadConfig {
+    fn default() -> Self {
+        Self {
+            concurrency: 4,
+            max_retries: 4,
+            base_delay: Duration::from_millis(500),
+            max_retry_after: Duration::from_secs(60),
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Summary {
+    pub fetched: usize,
+    pub already_cached: usize,
+    pub retries: u32,
+    pub bytes: u64,
+    /// (url, error)
+    pub failed: Vec<(String, String)>,
+}
+
+impl std::fmt::Display for Summary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} fetched ({} KiB, {} retries), {} already cached, {} failed",
+            self.fetched,
+            self.bytes / 1024,
+            self.retries,
+            self.already_cached,
+            self.failed.len()
+        )
+    }
+}
+
+enum Outcome {
+    Cached,
+    Fetched { bytes: u64, retries: u32 },
+    Failed { error: String, retries: u32 },
+}
+
+/// whether it's worth trying again, and how long the server asked us to wait if it said
+enum Failure {
+    Retry(String, Option<Duration>),
+    GiveUp(String),
+}
+
+/// fetches everything in `downloads` that isn't cached yet. blocks until they've all finished
+/// or run out of retries; failures are in the summary rather than an error
+pub fn download_all(
+    fetcher: Arc<dyn Fetcher>,
+    cache: Arc<AssetCache>,
+    downloads: Vec<Download>,
+    config: &DownloadConfig,
+) -> anyhow::Result<Summary> {
+    let runtime = tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()?;
+    runtime.block_on(download_all_async(fetcher, cache, downloads, config))
+}
+
+pub async fn download_all_async(
+    fetcher: Arc<dyn Fetcher>,
+    cache: Arc<AssetCache>,
+    downloads: Vec<Download>,
+    config: &DownloadConfig,
+) -> anyhow::Result<Summary> {
+    let permits = Arc::new(Semaphore::new(config.concurrency.max(1)));
+    let total = downloads.len();
+    let mut tasks = JoinSet::new();
+    for d in downloads {
+        let fetcher = fetcher.clone();
+        let cache = cache.clone();
+        let permits = permits.clone();
+        let config = config.clone();
+        tasks.spawn(async move {
+            if cache.contains(&d.key) {
+                return (d.url, Outcome::Cached);
+            }
+            let _permit = permits
+                .acquire_owned()
+                .await
+                .expect("semaphore never closed");
+            let outcome = fetch_to_cache(fetcher, cache, &d, &config).await;
+            (d.url, outcome)
+        });
+    }
+
+    let mut summary = Summary::default();
+    let mut done = 0;
+    while let Some(result) = tasks.join_next().await {
+        let (url, outcome) = result?;
+        done += 1;
+        match outcome {
+            Outcome::Cached => summary.already_cached += 1,
+            Outcome::Fetched { bytes, retries } => {
+                summary.fetched += 1;
+                summary.bytes += bytes;
+                summary.retries += retries;
+                println!("[{done}/{total}] {url}");
+            }
+            Outcome::Failed { error, retries } => {
+                summary.retries += retries;
+                println!("[{done}/{total}] {url} failed: {error}");
+                summary.failed.push((url, error));
+            }
+        }
+    }
+    Ok(summary)
+}
+
+async fn fetch_to_cache(
+    fetcher: Arc<dyn Fetcher>,
+    cache: Arc<AssetCache>,
+    d: &Download,
+    config: &DownloadConfig,
+) -> Outcome {
+    let mut retries = 0;
+    loop {
+        // fetchers block, so each attempt gets its own thread
+        let attempt = {
+            let fetcher = fetcher.clone();
+            let cache = cache.clone();
+            let d = d.clone();
+            tokio::task::spawn_blocking(move || fetch_once(fetcher.as_ref(), &cache, &d)).await
+        };
+        let failure = match attempt {
+            Ok(Ok(bytes)) => return Outcome::Fetched { bytes, retries },
+            Ok(Err(f)) => f,
+            Err(e) => Failure::GiveUp(e.to_string()),
+        };
+        let (error, wait) = match failure {
+            Failure::GiveUp(error) => return Outcome::Failed { error, retries },
+            Failure::Retry(e, wait) => (e, wait),
+        };
+        if retries >= config.max_retries {
+            return Outcome::Failed {
+                error: format!("{error} (gave up after {retries} retries)"),
+                retries,
+            };
+        }
+        let backoff = config.base_delay * 2u32.saturating_pow(retries);
+        let wait = wait.map_or(backoff, |w| w.min(config.max_retry_after).max(backoff));
+        tokio::time::sleep(wait).await;
+        retries += 1;
+    }
+}
+
+/// one attempt, caching the body if it worked. returns how many bytes were cached
+fn fetch_once(fetcher: &dyn Fetcher, cache: &AssetCache, d: &Download) -> Result<u64, Failure> {
+    let resp = fetcher.get(&d.url).map_err(|e| {
+        let transient = e
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_timeout() || e.is_connect() || e.is_request() || e.is_body());
+        if transient {
+            Failure::Retry(e.to_string(), None)
+        } else {
+            Failure::GiveUp(e.to_string())
+        }
+    })?;
+    if resp.status >= 500 || resp.status == 429 {
+        let wait = resp
+            .header("retry-after")
+            .and_then(|v| v.parse().ok())
+            .map(Duration::from_secs);
+        return Err(Failure::Retry(format!("status {}", resp.status), wait));
+    }
+    if !resp.is_success() {
+        return Err(Failure::GiveUp(format!("status {}", resp.status)));
+    }
+    if !resp.is_complete() {
+        return Err(Failure::Retry("body was cut off".to_string(), None));
+    }
+    (d.validate)(&resp.body).map_err(|e| Failure::Retry(format!("invalid body: {e}"), None))?;
+    cache
+        .insert(&d.key, &resp.body)
+        .map_err(|e| Failure::GiveUp(format!("Error caching: {e}")))?;
+    Ok(resp.body.len() as u64)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset_cache::validate_any;
+    use crate::fetch::FetchResponse;
+    use std::collections::HashMap;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Mutex;
+    use std::time::Instant;
+
+    /// answers each url with its list of responses in turn, repeating the last one. anything
+    /// it doesn't know is a 404
+    #[derive(Default)]
+    struct Scripted {
+        responses: HashMap<String, Vec<FetchResponse>>,
+        calls: Mutex<HashMap<String, usize>>,
+        delay: Duration,
+        in_flight: AtomicUsize,
+        max_in_flight: AtomicUsize,
+    }
+
+    impl Scripted {
+        fn with(mut self, url: &str, responses: Vec<FetchResponse>) -> Self {
+            self.responses.insert(url.to_string(), responses);
+            self
+        }
+
+        fn calls(&self, url: &str) -> usize {
+            self.calls.lock().unwrap().get(url).copied().unwrap_or(0)
+        }
+    }
+
+    impl Fetcher for Scripted {
+        fn fetch(&self, url: &str, _headers: &[(&str, &str)]) -> anyhow::Result<FetchResponse> {
+            let now = self.in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+            self.max_in_flight.fetch_max(now, Ordering::SeqCst);
+            std::thread::sleep(self.delay);
+            let n = {
+                let mut calls = self.calls.lock().unwrap();
+                let n = calls.entry(url.to_string()).or_default();
+                *n += 1;
+                *n - 1
+            };
+            let response = match self.responses.get(url) {
+                Some(list) => list[n.min(list.len() - 1)].clone(),
+                None => status(404),
+            };
+            self.in_flight.fetch_sub(1, Ordering::SeqCst);
+            Ok(response)
+        }
+    }
+
+    fn status(status: u16) -> FetchResponse {
+        FetchResponse {
+            status,
+            ..Default::default()
+        }
+    }
+
+    fn ok(body: &str) -> FetchResponse {
+        FetchResponse {
+            status: 200,
+            body: body.as_bytes().to_vec(),
+            ..Default::default()
+        }
+    }
+
+    fn cache(name: &str) -> Arc<AssetCache> {
+        let dir =
+            std::env::temp_dir().join(format!("image_misc_download_{name}_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        Arc::new(AssetCache::open(dir, None).unwrap())
+    }
+
+    fn quick() -> DownloadConfig {
+        DownloadConfig {
+            concurrency: 4,
+            max_retries: 3,
+            base_delay: Duration::from_millis(1),
+            max_retry_after: Duration::from_secs(2),
+        }
+    }
+
+    fn run<F: Fetcher + 'static>(
+        fetcher: &Arc<F>,
+        cache: &Arc<AssetCache>,
+        urls: &[&str],
+        config: &DownloadConfig,
+    ) -> Summary {
+        let downloads = urls
+            .iter()
+            .map(|u| Download::new(u, validate_any))
+            .collect();
+        download_all(fetcher.clone(), cache.clone(), downloads, config).unwrap()
+    }
+
+    #[test]
+    fn retries_server_errors_until_it_works() {
+        let fetcher =
+            Arc::new(Scripted::default().with("a", vec![status(503), status(500), ok("a")]));
+        let cache = cache("retry");
+        let summary = run(&fetcher, &cache, &["a"], &quick());
+        assert_eq!(summary.fetched, 1);
+        assert_eq!(summary.retries, 2);
+        assert!(summary.failed.is_empty());
+        assert_eq!(fetcher.calls("a"), 3);
+        assert_eq!(cache.get("a").unwrap().as_deref(), Some(&b"a"[..]));
+    }
+
+    #[test]
+    fn gives_up_on_404_straight_away() {
+        let fetcher = Arc::new(Scripted::default());
+        let cache = cache("404");
+        let summary = run(&fetcher, &cache, &["missing"], &quick());
+        assert_eq!(summary.fetched, 0);
+        assert_eq!(summary.retries, 0);
+        assert_eq!(summary.failed.len(), 1);
+        assert_eq!(fetcher.calls("missing"), 1);
+        assert!(!cache.contains("missing"));
+    }
+
+    #[test]
+    fn waits_as_long_as_retry_after_says() {
+        let mut busy = status(429);
+        busy.headers
+            .insert("retry-after".to_string(), "1".to_string());
+        let fetcher = Arc::new(Scripted::default().with("a", vec![busy, ok("a")]));
+        let cache = cache("retry_after");
+        let start = Instant::now();
+        let summary = run(&fetcher, &cache, &["a"], &quick());
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(summary.fetched, 1);
+        assert_eq!(summary.retries, 1);
+    }
+
+    #[test]
+    fn retry_after_is_capped() {
+        let mut busy = status(503);
+        busy.headers
+            .insert("retry-after".to_string(), "3600".to_string());
+        let fetcher = Arc::new(Scripted::default().with("a", vec![busy, ok("a")]));
+        let cache = cache("retry_after_cap");
+        let config = DownloadConfig {
+            max_retry_after: Duration::from_millis(10),
+            ..quick()
+        };
+        let start = Instant::now();
+        let summary = run(&fetcher, &cache, &["a"], &config);
+        assert!(start.elapsed() < Duration::from_secs(5));
+        assert_eq!(summary.fetched, 1);
+    }
+
+    /// what the local server does with each connection, in order. after the last one it stops
+    /// listening
+    enum Behaviour {
+        /// reads the request and never answers
+        Stall,
+        /// reads the request and hangs up
+        HangUp,
+        Answer(&'static str),
+    }
+
+    /// a plain http server on a local port, for the parts of the downloader that only a real
+    /// connection reaches. returns its base url
+    fn serve(behaviours: Vec<Behaviour>) -> String {
+        use std::io::{Read, Write};
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let mut stalled = vec![];
+            for behaviour in behaviours {
+                let (mut conn, _) = listener.accept().unwrap();
+                let mut request = vec![];
+                let mut buf = [0; 1024];
+                while !request.ends_with(b"\r\n\r\n") {
+                    match conn.read(&mut buf) {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => request.extend_from_slice(&buf[..n]),
+                    }
+                }
+                match behaviour {
+                    // kept open until the server's done, so the client has to time out
+                    Behaviour::Stall => stalled.push(conn),
+                    Behaviour::HangUp => drop(conn),
+                    Behaviour::Answer(body) => {
+                        let _ = write!(
+                            conn,
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                            body.len()
+                        );
+                    }
+                }
+            }
+            std::thread::sleep(Duration::from_secs(2));
+        });
+        url
+    }
+
+    #[test]
+    fn retries_timeouts_and_hang_ups_from_a_real_server() {
+        use crate::fetch::HttpFetcher;
+        let url = format!(
+            "{}/sprite.png",
+            serve(vec![
+                Behaviour::Stall,
+                Behaviour::HangUp,
+                Behaviour::Answer("png")
+            ])
+        );
+        let fetcher = Arc::new(HttpFetcher::new(Duration::from_millis(300)).unwrap());
+        let cache = cache("tcp");
+        let summary = run(&fetcher, &cache, &[&url], &quick());
+        assert!(summary.failed.is_empty(), "{:?}", summary.failed);
+        assert_eq!(summary.retries, 2);
+        assert_eq!(cache.get(&url).unwrap().as_deref(), Some(&b"png"[..]));
+    }
+
+    #[test]
+    fn retries_when_nothing_is_listening() {
+        use crate::fetch::HttpFetcher;
+        let url = {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            format!("http://{}/gone.png", listener.local_addr().unwrap())
+        };
+        let fetcher = Arc::new(HttpFetcher::new(Duration::from_millis(300)).unwrap());
+        let cache = cache("refused");
+        let config = DownloadConfig {
+            max_retries: 1,
+            ..quick()
+        };
+        let summary = run(&fetcher, &cache, &[&url], &config);
+        assert_eq!(summary.failed.len(), 1);
+        // a refused connection might work next time, so it's retried rather than given up on
+        assert_eq!(summary.retries, 1);
+    }
+
+    #[test]
+    fn never_has_more_than_concurrency_requests_going() {
+        let urls: Vec<String> = (0..9).map(|n| n.to_string()).collect();
+        let mut fetcher = Scripted {
+            delay: Duration::from_millis(50),
+            ..Default::default()
+        };
+        for u in &urls {
+            fetcher = fetcher.with(u, vec![ok(u)]);
+        }
+        let fetcher = Arc::new(fetcher);
+        let cache = cache("parallel");
+        let config = DownloadConfig {
+            concurrency: 3,
+            ..quick()
+        };
+        let urls: Vec<&str> = urls.iter().map(String::as_str).collect();
+        let summary = run(&fetcher, &cache, &urls, &config);
+        assert_eq!(summary.fetched, 9);
+        let most = fetcher.max_in_flight.load(Ordering::SeqCst);
+        assert!((2..=3).contains(&most), "{} requests at once", most);
+    }
+
+    #[test]
+    fn summary_adds_up() {
+        let fetcher = Arc::new(
+            Scripted::default()
+                .with("new", vec![status(502), ok("12345")])
+                .with("broken", vec![status(500)])
+                .with("cached", vec![ok("should not be fetched")]),
+        );
+        let cache = cache("summary");
+        cache.insert("cached", b"already here").unwrap();
+        let config = DownloadConfig {
+            max_retries: 2,
+            ..quick()
+        };
+        let summary = run(
+            &fetcher,
+            &cache,
+            &["new", "broken", "cached", "gone"],
+            &config,
+        );
+        assert_eq!(summary.fetched, 1);
+        assert_eq!(summary.already_cached, 1);
+        assert_eq!(summary.bytes, 5);
+        // one for "new", two for "broken" before it gave up
+        assert_eq!(summary.retries, 3);
+        let mut failed: Vec<&str> = summary.failed.iter().map(|(u, _)| u.as_str()).collect();
+        failed.sort();
+        assert_eq!(failed, ["broken", "gone"]);
+        assert_eq!(fetcher.calls("cached"), 0);
+        assert_eq!(
+            summary.to_string(),
+            "1 fetched (0 KiB, 3 retries), 1 already cached, 2 failed"
+        );
+    }
+}
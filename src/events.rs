@@ -0,0 +1,317 @@
+use crate::template_match::{find_any, TemplateMatcher};
+use crate::{env_var_opt, number_from_pathbuf};
+use image::{DynamicImage, GenericImageView, Pixel};
+use std::fmt::{Display, Formatter};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EventKind {
+    /// (nearly) black screen, e.g. fading between areas
+    Fade,
+    /// most of the screen changed from one frame to the next, e.g. scrolling to a new room
+    Transition,
+    /// one of the item get templates matched, i.e. link is holding something over his head
+    ItemGet,
+}
+
+impl Display for EventKind {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            EventKind::Fade => "FADE",
+            EventKind::Transition => "TRANSITION",
+            EventKind::ItemGet => "ITEM_GET",
+        };
+        write!(f, "{s}")
+    }
+}
+
+/// a run of consecutive frames that all had the same event.
+/// indexes are positions in the sorted list of every captured frame, before `SKIP_ALTERNATING`
+/// drops any, which is what `SKIP_IMAGES` counts. `start_frame`/`end_frame` are the capture's
+/// frame numbers, from the file names
+#[derive(Debug, Clone)]
+pub struct Event {
+    pub kind: EventKind,
+    pub start_index: usize,
+    pub end_index: usize,
+    pub start_frame: Option<u32>,
+    pub end_frame: Option<u32>,
+}
+
+impl Event {
+    pub fn len(&self) -> usize {
+        self.end_index - self.start_index + 1
+    }
+}
+
+pub struct EventThresholds {
+    /// average brightness (0-255) below which a frame counts as black
+    pub black_brightness: f64,
+    /// fraction of pixels that have to change between frames to count as a transition
+    pub transition_fraction: f64,
+    /// how much a channel has to change by for a pixel to count as changed
+    pub pixel_tolerance: u8,
+}
+
+impl EventThresholds {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let black_brightness = env_var_opt("BLACK_BRIGHTNESS")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(8.0);
+        let transition_fraction = env_var_opt("TRANSITION_FRACTION")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(0.6);
+        let pixel_tolerance = env_var_opt("PIXEL_TOLERANCE")
+            .map(|v| v.parse())
+            .transpose()?
+            .unwrap_or(16);
+        Ok(Self {
+            black_brightness,
+            transition_fraction,
+            pixel_tolerance,
+        })
+    }
+}
+
+/// average of (r + g + b) / 3 over the whole frame
+pub fn mean_brightness(image: &DynamicImage) -> f64 {
+    let total: u64 = image
+        .pixels()
+        .map(|(_, _, px)| px.to_rgb().0.iter().map(|c| *c as u64).sum::<u64>())
+        .sum();
+    let count = image.width() as u64 * image.height() as u64 * 3;
+    if count == 0 {
+        0.0
+    } else {
+        total as f64 / count as f64
+    }
+}
+
+/// fraction (0-1) of pixels where any channel differs by more than `tolerance`.
+/// frames of different sizes are considered completely different
+pub fn frame_difference(a: &DynamicImage, b: &DynamicImage, tolerance: u8) -> f64 {
+    if a.dimensions() != b.dimensions() {
+        return 1.0;
+    }
+    let total = a.width() as u64 * a.height() as u64;
+    if total == 0 {
+        return 0.0;
+    }
+    let changed = a
+        .pixels()
+        .zip(b.pixels())
+        .filter(|((_, _, pa), (_, _, pb))| {
+            pa.to_rgb()
+                .0
+                .iter()
+                .zip(pb.to_rgb().0.iter())
+                .any(|(ca, cb)| ca.abs_diff(*cb) > tolerance)
+        })
+        .count() as u64;
+    changed as f64 / total as f64
+}
+
+/// what `DETECT_EVENTS` looks for when there's no `ITEM_GET_TEMPLATE_DIR`: link holding
+/// something up with both hands, from `assets/item_get`. it's drawn in the green `find_link`
+/// looks for rather than cut from a capture, so a few pixels are allowed to be off
+pub fn standard_item_get_templates() -> anyhow::Result<Vec<TemplateMatcher>> {
+    let two_hands_up =
+        image::load_from_memory(include_bytes!("../assets/item_get/two_hands_up.png"))?;
+    Ok(vec![
+        TemplateMatcher::new("two_hands_up", &two_hands_up)?.with_max_mismatches(8)
+    ])
+}
+
+/// classifies a single frame. fades win over transitions, since fading to black is itself a
+/// big change from the previous frame
+fn classify(
+    image: &DynamicImage,
+    previous: Option<&DynamicImage>,
+    thresholds: &EventThresholds,
+    item_get_matchers: &[TemplateMatcher],
+) -> Option<EventKind> {
+    if mean_brightness(image) < thresholds.black_brightness {
+        return Some(EventKind::Fade);
+    }
+    if let Some(previous) = previous {
+        if frame_difference(previous, image, thresholds.pixel_tolerance)
+            > thresholds.transition_fraction
+        {
+            return Some(EventKind::Transition);
+        }
+    }
+    if find_any(item_get_matchers, image).is_some() {
+        return Some(EventKind::ItemGet);
+    }
+    None
+}
+
+/// runs over a frame sequence and merges consecutive frames with the same classification into
+/// events
+pub fn detect_events<I: Iterator<Item = (DynamicImage, PathBuf)>>(
+    images: I,
+    thresholds: &EventThresholds,
+    item_get_matchers: &[TemplateMatcher],
+) -> Vec<Event> {
+    let mut events: Vec<Event> = vec![];
+    let mut previous: Option<DynamicImage> = None;
+    for (index, (image, path)) in images.enumerate() {
+        let kind = classify(&image, previous.as_ref(), thresholds, item_get_matchers);
+        let frame = number_from_pathbuf(&path);
+        if let Some(kind) = kind {
+            match events.last_mut() {
+                Some(last) if last.kind == kind && last.end_index + 1 == index => {
+                    last.end_index = index;
+                    last.end_frame = frame;
+                }
+                _ => events.push(Event {
+                    kind,
+                    start_index: index,
+                    end_index: index,
+                    start_frame: frame,
+                    end_frame: frame,
+                }),
+            }
+        }
+        previous = Some(image);
+    }
+    events
+}
+
+fn frame_str(f: Option<u32>) -> String {
+    f.map(|f| f.to_string()).unwrap_or_else(|| "?".to_string())
+}
+
+/// one line per event, with the `SKIP_IMAGES`/`TAKE_IMAGES` values that would select it.
+/// `TAKE_IMAGES` counts frames after `SKIP_ALTERNATING`, so it's given both ways
+pub fn format_timeline(events: &[Event]) -> String {
+    events
+        .iter()
+        .map(|e| {
+            format!(
+                "{:<10} frames {}-{} (SKIP_IMAGES={} TAKE_IMAGES={}, or {} with SKIP_ALTERNATING=1)\n",
+                e.kind,
+                frame_str(e.start_frame),
+                frame_str(e.end_frame),
+                e.start_index,
+                e.len(),
+                e.len().div_ceil(2)
+            )
+        })
+        .collect()
+}
+
+pub fn timeline_csv(events: &[Event]) -> String {
+    let mut out = String::from("kind,start_index,end_index,start_frame,end_frame\n");
+    for e in events {
+        out.push_str(&format!(
+            "{},{},{},{},{}\n",
+            e.kind,
+            e.start_index,
+            e.end_index,
+            frame_str(e.start_frame),
+            frame_str(e.end_frame)
+        ));
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::imageops::{flip_vertical, overlay};
+    use image::{Rgba, RgbaImage};
+
+    /// a room's floor, with `link` standing on it if given
+    fn frame(link: Option<&RgbaImage>) -> DynamicImage {
+        let mut frame = RgbaImage::from_fn(256, 224, |x, y| {
+            if (x / 8 + y / 8) % 2 == 0 {
+                Rgba([144, 104, 64, 255])
+            } else {
+                Rgba([120, 88, 56, 255])
+            }
+        });
+        if let Some(link) = link {
+            overlay(&mut frame, link, 100, 90);
+        }
+        DynamicImage::ImageRgba8(frame)
+    }
+
+    fn two_hands_up() -> RgbaImage {
+        image::load_from_memory(include_bytes!("../assets/item_get/two_hands_up.png"))
+            .unwrap()
+            .to_rgba8()
+    }
+
+    fn thresholds() -> EventThresholds {
+        EventThresholds {
+            black_brightness: 8.0,
+            transition_fraction: 0.6,
+            pixel_tolerance: 16,
+        }
+    }
+
+    #[test]
+    fn an_item_pickup_is_found_and_nothing_else_is() {
+        let matchers = standard_item_get_templates().unwrap();
+        let hands_up = two_hands_up();
+        let hands_down = flip_vertical(&hands_up);
+        let t = thresholds();
+        assert_eq!(
+            classify(&frame(Some(&hands_up)), None, &t, &matchers),
+            Some(EventKind::ItemGet)
+        );
+        assert_eq!(
+            classify(&frame(Some(&hands_down)), None, &t, &matchers),
+            None
+        );
+        assert_eq!(classify(&frame(None), None, &t, &matchers), None);
+    }
+
+    #[test]
+    fn consecutive_frames_merge_into_events() {
+        let matchers = standard_item_get_templates().unwrap();
+        let hands_up = two_hands_up();
+        let black = DynamicImage::ImageRgba8(RgbaImage::from_pixel(256, 224, Rgba([0, 0, 0, 255])));
+        let frames = vec![
+            frame(None),
+            frame(Some(&hands_up)),
+            frame(Some(&hands_up)),
+            frame(None),
+            black.clone(),
+            black,
+            frame(None),
+        ];
+        let events = detect_events(
+            frames
+                .into_iter()
+                .enumerate()
+                .map(|(n, f)| (f, PathBuf::from(format!("{}.png", 100 + n)))),
+            &thresholds(),
+            &matchers,
+        );
+        let summary: Vec<_> = events
+            .iter()
+            .map(|e| {
+                (
+                    e.kind,
+                    e.start_index,
+                    e.end_index,
+                    e.start_frame,
+                    e.end_frame,
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (EventKind::ItemGet, 1, 2, Some(101), Some(102)),
+                (EventKind::Fade, 4, 5, Some(104), Some(105)),
+                // everything changes coming back from black
+                (EventKind::Transition, 6, 6, Some(106), Some(106)),
+            ]
+        );
+    }
+}
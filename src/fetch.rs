@@ -0,0 +1,286 @@
+//! everything that goes over http goes through a `Fetcher`, so the binaries can be run against
+//! saved responses instead of the real sites.
+//!
+//! `FETCH_MODE` picks which one `from_env` gives you:
+//! - `live` (the default) just makes the requests
+//! - `record` makes them and saves every response under `FETCH_FIXTURES` (default `fixtures`)
+//! - `replay` never touches the network and answers from what `record` saved
+
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct FetchResponse {
+    pub status: u16,
+    /// names are lowercase
+    pub headers: HashMap<String, String>,
+    #[serde(skip)]
+    pub body: Vec<u8>,
+}
+
+impl FetchResponse {
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(|v| v.as_str())
+    }
+
+    /// false if the server said how long the body was and we got less
+    pub fn is_complete(&self) -> bool {
+        self.header("content-length")
+            .and_then(|l| l.parse::<usize>().ok())
+            .is_none_or(|l| self.body.len() >= l)
+    }
+
+    pub fn text(&self) -> anyhow::Result<String> {
+        Ok(String::from_utf8(self.body.clone())?)
+    }
+
+    /// an error unless the status is 2xx
+    pub fn error_for_status(self, url: &str) -> anyhow::Result<Self> {
+        if self.is_success() {
+            Ok(self)
+        } else {
+            Err(anyhow!("{url} returned {}", self.status))
+        }
+    }
+}
+
+pub trait Fetcher: Send + Sync {
+    /// a GET with extra request headers. non-2xx responses come back as responses, not errors
+    fn fetch(&self, url: &str, headers: &[(&str, &str)]) -> anyhow::Result<FetchResponse>;
+
+    fn get(&self, url: &str) -> anyhow::Result<FetchResponse> {
+        self.fetch(url, &[])
+    }
+}
+
+pub struct HttpFetcher {
+    client: reqwest::blocking::Client,
+}
+
+impl HttpFetcher {
+    pub fn new(timeout: Duration) -> anyhow::Result<Self> {
+        Ok(Self {
+            client: reqwest::blocking::Client::builder()
+                .timeout(timeout)
+                .build()?,
+        })
+    }
+}
+
+impl Fetcher for HttpFetcher {
+    fn fetch(&self, url: &str, headers: &[(&str, &str)]) -> anyhow::Result<FetchResponse> {
+        let mut req = self.client.get(url);
+        for (name, value) in headers {
+            req = req.header(*name, *value);
+        }
+        let resp = req.send()?;
+        let status = resp.status().as_u16();
+        let headers = resp
+            .headers()
+            .iter()
+            .filter_map(|(k, v)| Some((k.as_str().to_string(), v.to_str().ok()?.to_string())))
+            .collect();
+        let body = resp.bytes()?.to_vec();
+        Ok(FetchResponse {
+            status,
+            headers,
+            body,
+        })
+    }
+}
+
+/// where a response for `url` is kept under `dir`: a folder for the host, then the last part of
+/// the path to make it findable, then a hash of the whole url so that urls which only differ in
+/// their query, or where one is a prefix of another, never share a file
+pub fn fixture_path(dir: &Path, url: &str) -> PathBuf {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let clean = |s: &str| -> String {
+        s.chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || "._-".contains(c) {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect()
+    };
+    let (host, path) = rest.split_once('/').unwrap_or((rest, ""));
+    let path = path.split(['?', '#']).next().unwrap_or_default();
+    let name = path.rsplit('/').find(|p| !p.is_empty()).unwrap_or("index");
+    dir.join(clean(host)).join(format!(
+        "{}-{}",
+        clean(name),
+        crate::asset_cache::content_hash(url.as_bytes())
+    ))
+}
+
+fn meta_path(body_path: &Path) -> PathBuf {
+    let mut p = body_path.as_os_str().to_owned();
+    p.push(".meta.json");
+    PathBuf::from(p)
+}
+
+/// answers from files saved by `RecordingFetcher`. a body with no `.meta.json` next to it is
+/// treated as a plain 200, so fixtures can also be written by hand; the error for a missing one
+/// says what it should be called
+pub struct FixtureFetcher {
+    dir: PathBuf,
+}
+
+impl FixtureFetcher {
+    pub fn new<P: Into<PathBuf>>(dir: P) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Fetcher for FixtureFetcher {
+    fn fetch(&self, url: &str, _headers: &[(&str, &str)]) -> anyhow::Result<FetchResponse> {
+        let path = fixture_path(&self.dir, url);
+        let body = std::fs::read(&path).map_err(|e| {
+            anyhow!(
+                "No fixture for {url} at {} ({e}). record one with FETCH_MODE=record",
+                path.display()
+            )
+        })?;
+        let mut response = match std::fs::read_to_string(meta_path(&path)) {
+            Ok(meta) => serde_json::from_str(&meta)?,
+            Err(_) => FetchResponse {
+                status: 200,
+                ..Default::default()
+            },
+        };
+        response.body = body;
+        Ok(response)
+    }
+}
+
+/// passes requests on to `inner` and saves what comes back where `FixtureFetcher` looks for it
+pub struct RecordingFetcher<F: Fetcher> {
+    inner: F,
+    dir: PathBuf,
+}
+
+impl<F: Fetcher> RecordingFetcher<F> {
+    pub fn new<P: Into<PathBuf>>(inner: F, dir: P) -> Self {
+        Self {
+            inner,
+            dir: dir.into(),
+        }
+    }
+}
+
+impl<F: Fetcher> Fetcher for RecordingFetcher<F> {
+    fn fetch(&self, url: &str, headers: &[(&str, &str)]) -> anyhow::Result<FetchResponse> {
+        let response = self.inner.fetch(url, headers)?;
+        // a 304 has no body worth keeping, and would replace the one we already have
+        if response.status != 304 {
+            let path = fixture_path(&self.dir, url);
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(&path, &response.body)?;
+            std::fs::write(meta_path(&path), serde_json::to_string_pretty(&response)?)?;
+        }
+        Ok(response)
+    }
+}
+
+/// see the module docs for `FETCH_MODE` and `FETCH_FIXTURES`
+pub fn from_env() -> anyhow::Result<Arc<dyn Fetcher>> {
+    let var = |k: &str| std::env::var(k).ok().filter(|v| !v.is_empty());
+    let dir = var("FETCH_FIXTURES").unwrap_or_else(|| "fixtures".to_string());
+    let live = || HttpFetcher::new(Duration::from_secs(30));
+    match var("FETCH_MODE").as_deref() {
+        None | Some("live") => Ok(Arc::new(live()?)),
+        Some("record") => {
+            println!("Recording responses to {dir}");
+            Ok(Arc::new(RecordingFetcher::new(live()?, dir)))
+        }
+        Some("replay") => {
+            println!("Replaying responses from {dir}");
+            Ok(Arc::new(FixtureFetcher::new(dir)))
+        }
+        Some(other) => Err(anyhow!("Unknown FETCH_MODE {other}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fixture_paths_never_collide() {
+        let dir = Path::new("fixtures");
+        let urls = [
+            "https://example.com/a",
+            "https://example.com/a/",
+            "https://example.com/a/b",
+            "https://example.com/a?x=1",
+            "https://example.com/a?x_1",
+            "https://example.com/",
+            "https://example.com/index",
+        ];
+        let paths: std::collections::HashSet<PathBuf> =
+            urls.iter().map(|u| fixture_path(dir, u)).collect();
+        assert_eq!(paths.len(), urls.len());
+        // nothing is ever a folder except the host, so no fixture can sit where another's folder is
+        for p in &paths {
+            assert_eq!(p.parent(), Some(dir.join("example.com").as_path()));
+        }
+        assert!(fixture_path(dir, "https://example.com/sprites.png?v=2")
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("sprites.png-"));
+    }
+
+    struct Canned;
+
+    impl Fetcher for Canned {
+        fn fetch(&self, url: &str, _headers: &[(&str, &str)]) -> anyhow::Result<FetchResponse> {
+            let mut headers = HashMap::new();
+            headers.insert("etag".to_string(), "\"1\"".to_string());
+            Ok(FetchResponse {
+                status: if url.ends_with("missing") { 404 } else { 200 },
+                headers,
+                body: url.as_bytes().to_vec(),
+            })
+        }
+    }
+
+    #[test]
+    fn replays_what_was_recorded() {
+        let dir = std::env::temp_dir().join(format!("image_misc_fetch_{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let recorder = RecordingFetcher::new(Canned, &dir);
+        let urls = [
+            "https://example.com/a",
+            "https://example.com/a/b",
+            "https://example.com/missing",
+        ];
+        for url in urls {
+            recorder.get(url).unwrap();
+        }
+        let replay = FixtureFetcher::new(&dir);
+        for url in urls {
+            let resp = replay.get(url).unwrap();
+            assert_eq!(resp.body, url.as_bytes());
+            assert_eq!(resp.header("ETag"), Some("\"1\""));
+        }
+        assert_eq!(
+            replay.get("https://example.com/missing").unwrap().status,
+            404
+        );
+        assert!(replay.get("https://example.com/never").is_err());
+    }
+}
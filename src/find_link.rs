@@ -1,4 +1,4 @@
-use image::{DynamicImage, GenericImageView, ImageBuffer, Pixel};
+use image::{DynamicImage, GenericImageView, Pixel};
 use std::collections::HashSet;
 use std::fmt::Display;
 
@@ -37,7 +37,7 @@ fn find_match(pixels: &HashSet<(i32, i32)>, reqd_offsets: &Vec<(i32, i32)>) -> O
 
     for pixel in pixels.iter() {
         if _is_match_starting_from(pixel) {
-            return Some(pixel.clone());
+            return Some(*pixel);
         }
     }
     None
@@ -49,6 +49,8 @@ fn find_match(pixels: &HashSet<(i32, i32)>, reqd_offsets: &Vec<(i32, i32)>) -> O
 ///
 /// expects standard 256x224 images. might be wrong in some cases
 struct FacingMatcher {
+    // only for telling the matchers apart when debugging
+    #[allow(dead_code)]
     name: String,
     hat_px_offsets: Vec<(i32, i32)>,
     link_offset_from_hat_found_location: (i32, i32),
@@ -79,7 +81,7 @@ impl FacingMatcher {
 }
 
 fn make_facing_matchers() -> Vec<FacingMatcher> {
-    let mut matchers = vec![
+    let matchers = vec![
         FacingMatcher::new(
             "facing_up_hat_top",
             vec![(1, 0), (2, 0), (3, 0), (4, 0), (-1, 1), (0, 1), (1, 1)],
@@ -0,0 +1,14 @@
+use ab_glyph::FontRef;
+
+/// one of the fonts in `fonts/`, by name
+pub fn bundled_font(name: &str) -> anyhow::Result<FontRef<'static>> {
+    let bytes: &'static [u8] = match name {
+        "arial" => include_bytes!("../fonts/ARIAL.TTF"),
+        "arial_bold" => include_bytes!("../fonts/ARIALBD.TTF"),
+        "arial_italic" => include_bytes!("../fonts/ARIALI.TTF"),
+        "arial_bold_italic" => include_bytes!("../fonts/ARIALBI.TTF"),
+        "arial_black" => include_bytes!("../fonts/ARIBLK.TTF"),
+        other => return Err(anyhow::anyhow!("Unknown font {other}")),
+    };
+    Ok(FontRef::try_from_slice(bytes)?)
+}
@@ -0,0 +1,139 @@
+use crate::annotate::{colour_from_env, draw_outlined_text};
+use crate::env_var_opt;
+use crate::manifest::CaptureManifest;
+use ab_glyph::FontRef;
+use anyhow::anyhow;
+use image::{DynamicImage, Rgba};
+use image_misc::fonts::bundled_font;
+use imageproc::drawing::text_size;
+
+/// ntsc snes frame rate
+const SNES_FPS: f64 = 60.0988;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StampContent {
+    Frame,
+    Time,
+    Both,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Corner {
+    TopLeft,
+    TopRight,
+    BottomLeft,
+    BottomRight,
+}
+
+impl Corner {
+    /// reads `top_left`, `top_right`, `bottom_left` or `bottom_right` from `key`
+    pub fn from_env(key: &str, default: Corner) -> anyhow::Result<Self> {
+        match env_var_opt(key).as_deref() {
+            None => Ok(default),
+            Some("top_left") => Ok(Corner::TopLeft),
+            Some("top_right") => Ok(Corner::TopRight),
+            Some("bottom_left") => Ok(Corner::BottomLeft),
+            Some("bottom_right") => Ok(Corner::BottomRight),
+            Some(other) => Err(anyhow!("Unknown {key} {other}")),
+        }
+    }
+
+    /// top left position for something `width` x `height` in this corner of the image
+    pub fn position(
+        &self,
+        image_width: u32,
+        image_height: u32,
+        width: u32,
+        height: u32,
+        margin: i32,
+    ) -> (i32, i32) {
+        let left = margin;
+        let right = image_width as i32 - width as i32 - margin;
+        let top = margin;
+        let bottom = image_height as i32 - height as i32 - margin;
+        match self {
+            Corner::TopLeft => (left, top),
+            Corner::TopRight => (right, top),
+            Corner::BottomLeft => (left, bottom),
+            Corner::BottomRight => (right, bottom),
+        }
+    }
+}
+
+/// stamps each frame with its source frame number and/or the time since the first frame
+pub struct FrameStamper {
+    content: StampContent,
+    corner: Corner,
+    size: f32,
+    colour: Rgba<u8>,
+    /// frame rate of the emulated system, for turning frame numbers into times
+    fps: f64,
+    font: FontRef<'static>,
+}
+
+impl FrameStamper {
+    /// `FRAME_STAMP` is `frame`, `time` or `both`. nothing is stamped if it's not set
+    pub fn from_env(manifest: Option<&CaptureManifest>) -> anyhow::Result<Option<Self>> {
+        let content = match env_var_opt("FRAME_STAMP").as_deref() {
+            None => return Ok(None),
+            Some("frame") => StampContent::Frame,
+            Some("time") => StampContent::Time,
+            Some("both") => StampContent::Both,
+            Some(other) => return Err(anyhow!("Unknown FRAME_STAMP {other}")),
+        };
+        let corner = Corner::from_env("FRAME_STAMP_CORNER", Corner::TopLeft)?;
+        let size = env_var_opt("FRAME_STAMP_SIZE")
+            .map(|s| s.parse())
+            .transpose()?
+            .unwrap_or(16.0);
+        let colour = colour_from_env("FRAME_STAMP_COLOUR")?.unwrap_or(Rgba([255, 255, 255, 255]));
+        let fps = env_var_opt("FRAME_STAMP_FPS")
+            .map(|s| s.parse())
+            .transpose()?
+            .unwrap_or_else(|| manifest.map_or(SNES_FPS, |m| m.fps));
+        let font = bundled_font(
+            env_var_opt("FRAME_STAMP_FONT")
+                .as_deref()
+                .unwrap_or("arial_bold"),
+        )?;
+        Ok(Some(Self {
+            content,
+            corner,
+            size,
+            colour,
+            fps,
+            font,
+        }))
+    }
+
+    fn text(&self, frame: u32, start_frame: u32) -> String {
+        let elapsed = frame.saturating_sub(start_frame) as f64 / self.fps;
+        let minutes = (elapsed / 60.0).floor() as u32;
+        let seconds = elapsed - minutes as f64 * 60.0;
+        let time = format!("{minutes}:{seconds:06.3}");
+        match self.content {
+            StampContent::Frame => frame.to_string(),
+            StampContent::Time => time,
+            StampContent::Both => format!("{frame} {time}"),
+        }
+    }
+
+    /// `frame` is the frame number from the file name and `start_frame` the one of the first frame
+    /// in the gif. frames without a number are left alone
+    pub fn stamp(
+        &self,
+        image: DynamicImage,
+        frame: Option<u32>,
+        start_frame: Option<u32>,
+    ) -> DynamicImage {
+        let Some(frame) = frame else {
+            return image;
+        };
+        let text = self.text(frame, start_frame.unwrap_or(frame));
+        let mut image = image.into_rgba8();
+        let (w, h) = text_size(self.size, &self.font, &text);
+        let (x, y) = self.corner.position(image.width(), image.height(), w, h, 2);
+        draw_outlined_text(&mut image, self.colour, x, y, self.size, &self.font, &text);
+        DynamicImage::ImageRgba8(image)
+    }
+}
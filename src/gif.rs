@@ -0,0 +1,35 @@
+use image::codecs::gif::{GifEncoder, Repeat};
+use image::{Delay, DynamicImage, Frame};
+use std::io::Write;
+use std::time::Duration;
+
+/// how long each captured frame is shown for. gif delays are in hundredths of a second, so
+/// alternating short and long delays is as close as they get to the real rate
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameDuration {
+    Fps30,
+    Fps60,
+}
+
+/// just writes the gif given the input images
+pub fn write_gif<W: Write, I: Iterator<Item = DynamicImage>>(
+    images: I,
+    mut out: W,
+    fd: FrameDuration,
+) -> anyhow::Result<()> {
+    let mut ge = GifEncoder::new_with_speed(&mut out, 1);
+    ge.set_repeat(Repeat::Infinite)?;
+
+    let (short_ms, long_ms) = match fd {
+        FrameDuration::Fps30 => (20, 40),
+        FrameDuration::Fps60 => (10, 20),
+    };
+    let short_delay = Delay::from_saturating_duration(Duration::from_millis(short_ms));
+    let long_delay = Delay::from_saturating_duration(Duration::from_millis(long_ms));
+
+    ge.encode_frames(images.enumerate().map(|(c, i)| {
+        let delay = if c % 2 == 0 { short_delay } else { long_delay };
+        Frame::from_parts(i.into_rgba8(), 0, 0, delay)
+    }))?;
+    Ok(())
+}
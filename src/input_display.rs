@@ -0,0 +1,193 @@
+use crate::env_var_opt;
+use crate::frame_stamp::Corner;
+use crate::manifest::CaptureManifest;
+use anyhow::anyhow;
+use image::{DynamicImage, Rgba, RgbaImage};
+use imageproc::drawing::{draw_filled_circle_mut, draw_filled_rect_mut};
+use imageproc::rect::Rect;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+
+/// snes buttons, in the order they appear in the input log (bizhawk's `UDLRsSYBXAlr`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Button {
+    Up,
+    Down,
+    Left,
+    Right,
+    Select,
+    Start,
+    Y,
+    B,
+    X,
+    A,
+    L,
+    R,
+}
+
+const BUTTONS: [Button; 12] = [
+    Button::Up,
+    Button::Down,
+    Button::Left,
+    Button::Right,
+    Button::Select,
+    Button::Start,
+    Button::Y,
+    Button::B,
+    Button::X,
+    Button::A,
+    Button::L,
+    Button::R,
+];
+
+/// the buttons held on one frame
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Buttons(u16);
+
+impl Buttons {
+    pub fn held(&self, b: Button) -> bool {
+        self.0 & (1 << b as u16) != 0
+    }
+
+    /// parses a mnemonic like `U...........`, where any character other than `.` or space
+    /// means the button in that position is held
+    pub fn from_mnemonic(s: &str) -> anyhow::Result<Self> {
+        if s.chars().count() != BUTTONS.len() {
+            return Err(anyhow!("Expected {} buttons in `{s}`", BUTTONS.len()));
+        }
+        let mut bits = 0;
+        for (c, b) in s.chars().zip(BUTTONS.iter()) {
+            if c != '.' && c != ' ' {
+                bits |= 1 << *b as u16;
+            }
+        }
+        Ok(Self(bits))
+    }
+}
+
+/// inputs by frame number, as written by `bizhawk/screenshot-every-frame.lua`
+pub fn parse_input_log(contents: &str) -> anyhow::Result<HashMap<u32, Buttons>> {
+    let mut out = HashMap::new();
+    for (n, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let (frame, mnemonic) = line
+            .split_once(' ')
+            .ok_or_else(|| anyhow!("Bad input log line {}: `{line}`", n + 1))?;
+        out.insert(frame.parse()?, Buttons::from_mnemonic(mnemonic.trim())?);
+    }
+    Ok(out)
+}
+
+const BODY: Rgba<u8> = Rgba([70, 70, 70, 255]);
+const UNPRESSED: Rgba<u8> = Rgba([30, 30, 30, 255]);
+const PRESSED: Rgba<u8> = Rgba([235, 235, 235, 255]);
+
+/// pressed colours of the face buttons, as on the japanese/european controller
+fn face_colour(b: Button) -> Rgba<u8> {
+    match b {
+        Button::A => Rgba([220, 40, 40, 255]),
+        Button::B => Rgba([240, 200, 30, 255]),
+        Button::X => Rgba([40, 80, 220, 255]),
+        Button::Y => Rgba([40, 170, 60, 255]),
+        _ => PRESSED,
+    }
+}
+
+/// a small snes controller drawn in a corner of each frame, lit up with that frame's inputs
+pub struct InputDisplay {
+    inputs: HashMap<u32, Buttons>,
+    corner: Corner,
+    scale: u32,
+}
+
+impl InputDisplay {
+    // size of the controller at scale 1
+    const WIDTH: u32 = 64;
+    const HEIGHT: u32 = 28;
+
+    /// reads the log at `INPUT_LOG`, or the one named in the capture manifest when
+    /// `INPUT_DISPLAY=1`
+    pub fn from_env(manifest: Option<&CaptureManifest>) -> anyhow::Result<Option<Self>> {
+        let path = match env_var_opt("INPUT_LOG") {
+            Some(p) => PathBuf::from(p),
+            None if env_var_opt("INPUT_DISPLAY").as_deref() == Some("1") => manifest
+                .and_then(|m| m.input_log_path())
+                .ok_or_else(|| anyhow!("INPUT_DISPLAY=1 but the capture has no input log"))?,
+            None => return Ok(None),
+        };
+        let inputs = parse_input_log(&read_to_string(&path)?)
+            .map_err(|e| anyhow!("Error reading input log {path:?}: {e}"))?;
+        let corner = Corner::from_env("INPUT_DISPLAY_CORNER", Corner::BottomRight)?;
+        let scale = env_var_opt("INPUT_DISPLAY_SCALE")
+            .map(|s| s.parse())
+            .transpose()?
+            .unwrap_or(1);
+        Ok(Some(Self {
+            inputs,
+            corner,
+            scale,
+        }))
+    }
+
+    /// frames with no number or no logged input are left alone
+    pub fn draw(&self, image: DynamicImage, frame: Option<u32>) -> DynamicImage {
+        let Some(buttons) = frame.and_then(|f| self.inputs.get(&f)) else {
+            return image;
+        };
+        let mut image = image.into_rgba8();
+        let s = self.scale as i32;
+        let (ox, oy) = self.corner.position(
+            image.width(),
+            image.height(),
+            Self::WIDTH * self.scale,
+            Self::HEIGHT * self.scale,
+            2,
+        );
+        let rect = |image: &mut RgbaImage, x: i32, y: i32, w: u32, h: u32, c: Rgba<u8>| {
+            draw_filled_rect_mut(
+                image,
+                Rect::at(ox + x * s, oy + y * s).of_size(w * self.scale, h * self.scale),
+                c,
+            );
+        };
+        let lit = |b: Button, pressed: Rgba<u8>| {
+            if buttons.held(b) {
+                pressed
+            } else {
+                UNPRESSED
+            }
+        };
+
+        rect(&mut image, 4, 0, 14, 4, lit(Button::L, PRESSED));
+        rect(&mut image, 46, 0, 14, 4, lit(Button::R, PRESSED));
+        rect(&mut image, 0, 4, Self::WIDTH, Self::HEIGHT - 4, BODY);
+
+        rect(&mut image, 11, 13, 6, 6, UNPRESSED);
+        rect(&mut image, 11, 7, 6, 6, lit(Button::Up, PRESSED));
+        rect(&mut image, 11, 19, 6, 6, lit(Button::Down, PRESSED));
+        rect(&mut image, 5, 13, 6, 6, lit(Button::Left, PRESSED));
+        rect(&mut image, 17, 13, 6, 6, lit(Button::Right, PRESSED));
+
+        rect(&mut image, 25, 17, 6, 3, lit(Button::Select, PRESSED));
+        rect(&mut image, 33, 17, 6, 3, lit(Button::Start, PRESSED));
+
+        for (b, x, y) in [
+            (Button::X, 50, 9),
+            (Button::Y, 44, 16),
+            (Button::A, 56, 16),
+            (Button::B, 50, 23),
+        ] {
+            draw_filled_circle_mut(
+                &mut image,
+                (ox + x * s, oy + y * s),
+                3 * s,
+                lit(b, face_colour(b)),
+            );
+        }
+        DynamicImage::ImageRgba8(image)
+    }
+}
@@ -0,0 +1,10 @@
+//! the bits that are shared between the main tool and the other binaries
+
+pub mod aspect;
+pub mod asset_cache;
+pub mod download;
+pub mod fetch;
+pub mod fonts;
+pub mod gif;
+pub mod pixel_scale;
+pub mod zspr;
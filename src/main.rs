@@ -1,42 +1,76 @@
+mod align;
+mod annotate;
+mod clips;
+mod compare;
+mod contact_sheet;
+mod crt;
+mod events;
 mod find_link;
+mod frame_stamp;
+mod input_display;
+mod manifest;
+mod onion;
+mod overlays;
+mod positions;
+mod template_match;
 
+use crate::clips::{motion_scores, suggest_clips, ClipConfig};
+use crate::compare::{align_sources, composite, get_synced_images, ComparisonSource, Layout};
+use crate::contact_sheet::{contact_sheet, SheetLayout};
+use crate::crt::CrtFilter;
+use crate::events::{
+    detect_events, format_timeline, standard_item_get_templates, timeline_csv, EventThresholds,
+};
 use crate::find_link::find_link;
-use crate::FrameDuration::{Fps30, Fps60};
+use crate::manifest::CaptureManifest;
+use crate::onion::{onion_skin, Background, OnionConfig};
+use crate::overlays::Overlays;
+use crate::positions::{load_positions, LinkPosition, PositionCorrection};
+use crate::template_match::{find_any, load_templates, TemplateMatcher};
 use anyhow::anyhow;
-use image::codecs::gif::{GifEncoder, Repeat};
-use image::imageops::{crop, FilterType};
-use image::{Delay, DynamicImage, Frame};
+use image::imageops::FilterType;
+use image::DynamicImage;
+use image_misc::aspect::{correct_aspect, PixelAspect};
+use image_misc::fonts::bundled_font;
+use image_misc::gif::write_gif;
+use image_misc::gif::FrameDuration::{self, Fps30, Fps60};
+use image_misc::pixel_scale::{upscale, ScaleFilter};
 use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::fs::{read_dir, File};
-use std::io::Write;
 use std::path::{Path, PathBuf};
 use std::str::FromStr;
-use std::time::Duration;
 use std::{env, fs};
 
-fn number_from_pathbuf(p: &PathBuf) -> Option<u32> {
+fn number_from_pathbuf(p: &Path) -> Option<u32> {
     p.file_stem()
         .and_then(|fs| fs.to_str())
         .and_then(|s| u32::from_str(s).ok())
 }
 
-fn compare_paths(a: &PathBuf, b: &PathBuf) -> Ordering {
-    number_from_pathbuf(&a).cmp(&number_from_pathbuf(&b))
+fn compare_paths(a: &Path, b: &Path) -> Ordering {
+    number_from_pathbuf(a).cmp(&number_from_pathbuf(b))
 }
 
+#[allow(clippy::expect_fun_call)]
 fn env_var(k: &str) -> String {
     env::var(k).expect(&format!("Failed to find required environment variable {k}"))
 }
 
+fn env_var_opt(k: &str) -> Option<String> {
+    env::var(k).ok().filter(|v| !v.is_empty())
+}
+
 fn readdir_to_sorted(path: &str) -> anyhow::Result<Vec<PathBuf>> {
     let mut filenames = read_dir(path)?
         .map(|f| f.unwrap().path())
         .filter(|p| !p.is_dir())
         .collect::<Vec<PathBuf>>();
-    filenames.sort_by(compare_paths);
+    filenames.sort_by(|a, b| compare_paths(a, b));
     Ok(filenames)
 }
 
+#[derive(Clone, Copy)]
 struct ImageSelectionConfig {
     skip: usize,
     take: usize,
@@ -55,11 +89,7 @@ impl ImageSelectionConfig {
     fn from_env() -> anyhow::Result<Self> {
         let skip = env_var("SKIP_IMAGES").parse()?;
         let take = env_var("TAKE_IMAGES").parse()?;
-        let skip_alternating = if env_var("SKIP_ALTERNATING").parse::<u32>()? == 1 {
-            true
-        } else {
-            false
-        };
+        let skip_alternating = env_var("SKIP_ALTERNATING").parse::<u32>()? == 1;
         Ok(Self {
             skip,
             take,
@@ -71,7 +101,14 @@ impl ImageSelectionConfig {
 fn get_images(
     isc: ImageSelectionConfig,
 ) -> anyhow::Result<impl Iterator<Item = (DynamicImage, PathBuf)>> {
-    let dir = env_var("IMAGE_DIR");
+    get_images_from(&env_var("IMAGE_DIR"), isc)
+}
+
+/// like `get_images`, but for `images/{dir}` instead of `IMAGE_DIR`
+fn get_images_from(
+    dir: &str,
+    isc: ImageSelectionConfig,
+) -> anyhow::Result<impl Iterator<Item = (DynamicImage, PathBuf)>> {
     let dir_path = format!("images/{dir}");
     let skip_alternating = isc.skip_alternating;
     let fmap = move |(c, i)| {
@@ -85,7 +122,14 @@ fn get_images(
             Some(i)
         }
     };
-    let image_paths = readdir_to_sorted(&dir_path)?;
+    let manifest = CaptureManifest::load(dir)?;
+    // only numbered files are frames. this skips e.g. the manifest and input log the capture
+    // script writes next to them
+    let image_paths: Vec<PathBuf> = readdir_to_sorted(&dir_path)?
+        .into_iter()
+        .filter(|p| number_from_pathbuf(p).is_some())
+        .filter(|p| manifest.as_ref().is_none_or(|m| m.is_frame(p)))
+        .collect();
     Ok(image_paths
         .into_iter()
         .skip(isc.skip)
@@ -101,16 +145,50 @@ fn main() -> anyhow::Result<()> {
     if op == "CROP_AROUND_LINK" {
         crop_around_link()?;
     }
+    if op == "CROP_AROUND_SPRITE" {
+        crop_around_sprite()?;
+    }
+    if op == "DETECT_EVENTS" {
+        detect_frame_events()?;
+    }
+    if op == "SUGGEST_CLIPS" {
+        suggest_gif_clips()?;
+    }
+    if op == "MAKE_COMPARISON_GIF" {
+        make_comparison_gif()?;
+    }
+    if op == "ALIGN_CAPTURES" {
+        align_captures()?;
+    }
+    if op == "ONION_SKIN" {
+        make_onion_skin()?;
+    }
+    if op == "CONTACT_SHEET" {
+        make_contact_sheet()?;
+    }
+    if op == "MAKE_GIF_WITH_CROP" {
+        make_gif_with_crop()?;
+    }
     if op == "MAKE_GIF" {
         let isc = ImageSelectionConfig::from_env()?;
-        let fps = if env_var("FPS").parse::<u32>()? == 30 {
-            Fps30
-        } else {
-            Fps60
-        };
         let dir = env_var("IMAGE_DIR");
+        let manifest = CaptureManifest::load(&dir)?;
+        if let Some(m) = &manifest {
+            println!(
+                "Capture of {} ({}) starting at emulator frame {}",
+                m.rom_name, m.system, m.capture_start_frame
+            );
+        }
+        let fps = frame_duration_from_env(manifest.as_ref(), isc.skip_alternating)?;
         let out_path = format!("images/out/{dir}.gif");
-        let images = get_images(isc)?.map(|(i, p)| i.resize(112, 112, FilterType::Nearest));
+        let scale_filter = scale_filter_from_env()?;
+        let aspect = AspectCorrection::from_env()?;
+        let crt = CrtFilter::from_env(output_scale_factor(scale_filter, aspect.as_ref()))?;
+        let images =
+            Overlays::from_env(manifest.as_ref())?.apply(get_images(isc)?.map(|(i, p)| {
+                let i = scale_for_output(i, scale_filter, aspect.as_ref(), (112, 112));
+                (apply_crt(i, crt.as_ref()), p)
+            }));
         let f = File::create(&out_path)?;
         println!("Writing file to {out_path}");
         write_gif(images, f, fps)?;
@@ -118,44 +196,104 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// link's logged positions from `LINK_POSITIONS`, or the capture manifest's positions log,
+/// moved to line up with what `find_link` returns. empty if there aren't any.
+///
+/// the move is `LINK_POSITION_OFFSET` (`x,y`) if it's set. otherwise it's measured, for each way
+/// link faces, on up to `CALIBRATION_FRAMES` frames of the capture where `find_link` finds him
+/// and he isn't moving between rooms
+fn positions_from_env(dir: &str) -> anyhow::Result<HashMap<u32, LinkPosition>> {
+    let path = match env_var_opt("LINK_POSITIONS") {
+        Some(p) => Some(PathBuf::from(p)),
+        None => CaptureManifest::load(dir)?.and_then(|m| m.positions_log_path()),
+    };
+    let positions = match path {
+        Some(p) => load_positions(p)?,
+        None => return Ok(HashMap::new()),
+    };
+    let correction = match env_var_opt("LINK_POSITION_OFFSET") {
+        Some(o) => {
+            let (x, y) = o
+                .split_once(',')
+                .ok_or_else(|| anyhow!("LINK_POSITION_OFFSET should be `x,y`, not `{o}`"))?;
+            PositionCorrection::fixed((x.trim().parse()?, y.trim().parse()?))
+        }
+        None => {
+            const CALIBRATION_FRAMES: usize = 120;
+            let pairs = get_images_from(dir, ImageSelectionConfig::blank())?
+                .filter_map(|(i, p)| {
+                    let frame = number_from_pathbuf(&p)?;
+                    let logged = *positions.get(&frame)?;
+                    let previous = frame.checked_sub(1).and_then(|f| positions.get(&f));
+                    if previous.is_some_and(|prev| logged.changed_room(prev)) {
+                        return None;
+                    }
+                    Some((find_link(&i)?, logged))
+                })
+                .take(CALIBRATION_FRAMES);
+            match PositionCorrection::measure(pairs) {
+                Some(c) => {
+                    println!(
+                        "Logged positions are {:?} off from find_link ({:?} by direction), correcting for it",
+                        c.overall, c.by_direction
+                    );
+                    c
+                }
+                None => {
+                    println!(
+                        "find_link didn't find link on any logged frame, so the logged positions are used as they are. set LINK_POSITION_OFFSET to correct them"
+                    );
+                    PositionCorrection::fixed((0, 0))
+                }
+            }
+        }
+    };
+    Ok(positions
+        .into_iter()
+        .map(|(frame, pos)| (frame, correction.apply(pos)))
+        .collect())
+}
+
+/// link's top left from the logged positions if this frame has one, otherwise from the pixels
+fn locate_link(
+    positions: &HashMap<u32, LinkPosition>,
+    i: &DynamicImage,
+    p: &Path,
+) -> Option<(i32, i32)> {
+    number_from_pathbuf(p)
+        .and_then(|n| positions.get(&n))
+        .map(|pos| (pos.x, pos.y))
+        .or_else(|| find_link(i))
+}
+
 fn crop_around_link() -> anyhow::Result<()> {
     let dir = env_var("IMAGE_DIR");
     let out_dir = format!("images/{dir}/link_crops");
 
     let width: i32 = env_var("CROP_WIDTH").parse()?;
+    // unlike CROP_AROUND_SPRITE, link crops have always been CROP_WIDTH square. CROP_HEIGHT only
+    // moves the box up or down, and is kept that way so old settings give the same crops
     let height: i32 = env_var("CROP_HEIGHT").parse()?;
 
+    let positions = positions_from_env(&dir)?;
+    let aspect = AspectCorrection::from_env()?;
     fs::create_dir_all(&out_dir)?;
     for (i, p) in get_images(ImageSelectionConfig::blank())? {
         assert_eq!(i.width(), 256);
         assert_eq!(i.height(), 224);
-        match find_link(&i) {
+        match locate_link(&positions, &i, &p) {
             Some((x, y)) => {
                 let mut out_path = PathBuf::from_str(&out_dir)?;
                 out_path.push(p.file_name().unwrap());
                 // link seems to be 16x24, for context
-                // we're getting the top left corner of link
-                // to get to `width` pixels, we want to go `width/2` pixels from the middle of 8
-                // so that's `(x + 8) - width/2`
-                // similarly, height will be `(y + 12) - width/2`
-                // and then we need to bound these to the dimensions of the source image
-                // which i am assuming here for convenience are 256 x 224
-                let mut topleft_x = ((x + 8) - (width as i32 / 2)).clamp(0, 256);
-                if topleft_x + width > 256 {
-                    topleft_x = (256 - width) as i32;
+                // we're getting the top left corner of link, so his middle is at (x + 8, y + 12)
+                // the crop is `width` x `width`: CROP_HEIGHT only decides where it sits
+                let (topleft_x, topleft_y) = centred_origin(&i, x + 8, y + 12, width, height);
+                let cropped = i.crop_imm(topleft_x, topleft_y, width as u32, width as u32);
+                match &aspect {
+                    Some(a) => a.apply(&cropped, None).save(&out_path)?,
+                    None => cropped.save(&out_path)?,
                 }
-                let mut topleft_y = ((y + 12) - (height as i32 / 2)).clamp(0, 224);
-                if topleft_y + height > 224 {
-                    topleft_y = (224 - height) as i32;
-                }
-
-                i.crop_imm(
-                    topleft_x as u32,
-                    topleft_y as u32,
-                    width as u32,
-                    width as u32,
-                )
-                .save(&out_path)?;
             }
             None => {
                 println!("unable to find link in {p:?}");
@@ -165,6 +303,328 @@ fn crop_around_link() -> anyhow::Result<()> {
     Ok(())
 }
 
+/// like `crop_around_link` but for any sprite: crops around the first match of the templates
+/// given by `TEMPLATE_PATH` (a single png) or `TEMPLATE_DIR` (every png in it, first match wins)
+fn crop_around_sprite() -> anyhow::Result<()> {
+    let dir = env_var("IMAGE_DIR");
+    let width: i32 = env_var("CROP_WIDTH").parse()?;
+    let height: i32 = env_var("CROP_HEIGHT").parse()?;
+    let tolerance: u8 = env_var_opt("MATCH_TOLERANCE")
+        .map(|t| t.parse())
+        .transpose()?
+        .unwrap_or(0);
+    let max_mismatches: usize = env_var_opt("MATCH_MAX_MISMATCHES")
+        .map(|t| t.parse())
+        .transpose()?
+        .unwrap_or(0);
+
+    let (name, matchers) = if let Some(path) = env_var_opt("TEMPLATE_PATH") {
+        let matcher = TemplateMatcher::from_file(path)?;
+        (matcher.name.clone(), vec![matcher])
+    } else {
+        let template_dir = env_var("TEMPLATE_DIR");
+        let name = Path::new(&template_dir)
+            .file_name()
+            .and_then(|n| n.to_str())
+            .unwrap_or("template")
+            .to_string();
+        (name, load_templates(&template_dir)?)
+    };
+    if matchers.is_empty() {
+        return Err(anyhow!("No templates found"));
+    }
+    let matchers: Vec<TemplateMatcher> = matchers
+        .into_iter()
+        .map(|m| {
+            m.with_tolerance(tolerance)
+                .with_max_mismatches(max_mismatches)
+        })
+        .collect();
+
+    let out_dir = format!("images/{dir}/{name}_crops");
+    let aspect = AspectCorrection::from_env()?;
+    fs::create_dir_all(&out_dir)?;
+    for (i, p) in get_images(ImageSelectionConfig::blank())? {
+        match find_any(&matchers, &i) {
+            Some((m, (x, y))) => {
+                let mut out_path = PathBuf::from_str(&out_dir)?;
+                out_path.push(p.file_name().unwrap());
+                let middle_x = x + m.width() as i32 / 2;
+                let middle_y = y + m.height() as i32 / 2;
+                let cropped = crop_centred(&i, middle_x, middle_y, width, height);
+                match &aspect {
+                    Some(a) => a.apply(&cropped, None).save(&out_path)?,
+                    None => cropped.save(&out_path)?,
+                }
+            }
+            None => {
+                println!("unable to find {name} in {p:?}");
+            }
+        }
+    }
+    Ok(())
+}
+
+/// prints a timeline of fades, transitions and item gets over the whole capture, and writes it
+/// to `images/out/{dir}_events.csv`. item gets are found with the pngs in
+/// `ITEM_GET_TEMPLATE_DIR`, or the ones that ship in `assets/item_get`
+fn detect_frame_events() -> anyhow::Result<()> {
+    let dir = env_var("IMAGE_DIR");
+    let thresholds = EventThresholds::from_env()?;
+    let item_get_matchers = match env_var_opt("ITEM_GET_TEMPLATE_DIR") {
+        Some(d) => load_templates(d)?,
+        None => standard_item_get_templates()?,
+    };
+    let events = detect_events(
+        get_images(ImageSelectionConfig::blank())?,
+        &thresholds,
+        &item_get_matchers,
+    );
+    print!("{}", format_timeline(&events));
+
+    let out_path = format!("images/out/{dir}_events.csv");
+    fs::create_dir_all("images/out")?;
+    fs::write(&out_path, timeline_csv(&events))?;
+    println!("Wrote {} events to {out_path}", events.len());
+    Ok(())
+}
+
+/// scores motion over the whole capture and prints the frame ranges of anything that moves.
+/// with `RENDER_CLIPS=1` each suggestion is also written out as `images/out/{dir}_clip_{n}.gif`
+fn suggest_gif_clips() -> anyhow::Result<()> {
+    let dir = env_var("IMAGE_DIR");
+    let config = ClipConfig::from_env()?;
+    let scores = motion_scores(
+        get_images(ImageSelectionConfig::blank())?.map(|(i, _)| i),
+        config.pixel_tolerance,
+    );
+    let clips = suggest_clips(&scores, &config);
+    let render = env_var_opt("RENDER_CLIPS").as_deref() == Some("1");
+    let out_size: Option<(u32, u32)> = match (env_var_opt("OUT_WIDTH"), env_var_opt("OUT_HEIGHT")) {
+        (Some(w), Some(h)) => Some((w.parse()?, h.parse()?)),
+        _ => None,
+    };
+    for (n, clip) in clips.iter().enumerate() {
+        println!(
+            "clip {n}: SKIP_IMAGES={} TAKE_IMAGES={}",
+            clip.start, clip.len
+        );
+        if !render {
+            continue;
+        }
+        let isc = ImageSelectionConfig {
+            skip: clip.start,
+            take: clip.len,
+            skip_alternating: false,
+        };
+        let images = get_images(isc)?.map(|(i, _)| match out_size {
+            Some((w, h)) => i.resize(w, h, FilterType::Nearest),
+            None => i,
+        });
+        fs::create_dir_all("images/out")?;
+        let out_path = format!("images/out/{dir}_clip_{n}.gif");
+        println!("Writing file to {out_path}");
+        write_gif(images, File::create(&out_path)?, Fps60)?;
+    }
+    Ok(())
+}
+
+/// renders every directory in `IMAGE_DIRS` into one gif, frame by frame, laid out according to
+/// `LAYOUT`
+fn make_comparison_gif() -> anyhow::Result<()> {
+    let mut sources = ComparisonSource::all_from_env()?;
+    let layout = Layout::from_env()?;
+    let isc = ImageSelectionConfig::from_env()?;
+    if env_var_opt("FRAME_OFFSETS").as_deref() == Some("auto") {
+        align_sources_from_env(&mut sources, &isc)?;
+    }
+    let spacing: u32 = env_var_opt("SPACING")
+        .map(|s| s.parse())
+        .transpose()?
+        .unwrap_or(2);
+    let label_size: f32 = env_var_opt("LABEL_SIZE")
+        .map(|s| s.parse())
+        .transpose()?
+        .unwrap_or(16.0);
+    let fps = frame_duration_from_env(
+        CaptureManifest::load(&sources[0].dir)?.as_ref(),
+        isc.skip_alternating,
+    )?;
+    let font = bundled_font("arial_bold")?;
+    let labels: Vec<Option<&str>> = sources.iter().map(|s| s.label.as_deref()).collect();
+
+    let frames = get_synced_images(&sources, &isc)?
+        .map(|frames| composite(&frames, &labels, &layout, spacing, &font, label_size))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+
+    let name = env_var_opt("COMPARISON_NAME").unwrap_or_else(|| {
+        sources
+            .iter()
+            .map(|s| s.dir.as_str())
+            .collect::<Vec<_>>()
+            .join("_vs_")
+    });
+    fs::create_dir_all("images/out")?;
+    let out_path = format!("images/out/{name}.gif");
+    println!("Writing file to {out_path}");
+    write_gif(frames.into_iter(), File::create(&out_path)?, fps)?;
+    Ok(())
+}
+
+/// prints the `FRAME_OFFSETS` that line up the captures in `IMAGE_DIRS`
+fn align_captures() -> anyhow::Result<()> {
+    let mut sources = ComparisonSource::all_from_env()?;
+    let isc = ImageSelectionConfig::from_env()?;
+    align_sources_from_env(&mut sources, &isc)?;
+    let offsets: Vec<String> = sources.iter().map(|s| s.offset.to_string()).collect();
+    println!("FRAME_OFFSETS={}", offsets.join(","));
+    Ok(())
+}
+
+/// `ALIGN_MAX_OFFSET` and `ALIGN_WINDOW` are in captured frames, whatever `SKIP_ALTERNATING`
+/// is. with `ALIGN_CROP=1` only the `CROP_X`/`CROP_Y`/`CROP_WIDTH`/`CROP_HEIGHT` region is
+/// compared
+fn align_sources_from_env(
+    sources: &mut [ComparisonSource],
+    isc: &ImageSelectionConfig,
+) -> anyhow::Result<()> {
+    let max_offset: usize = env_var_opt("ALIGN_MAX_OFFSET")
+        .map(|s| s.parse())
+        .transpose()?
+        .unwrap_or(60);
+    let window: usize = env_var_opt("ALIGN_WINDOW")
+        .map(|s| s.parse())
+        .transpose()?
+        .unwrap_or(30);
+    let cropper = if env_var_opt("ALIGN_CROP").as_deref() == Some("1") {
+        Some(Cropper::new_from_env()?)
+    } else {
+        None
+    };
+    align_sources(sources, isc, max_offset, window, cropper.as_ref())
+}
+
+/// draws link's position from every `ONION_STRIDE`th selected frame onto one still, written to
+/// `images/out/{dir}_onion.png`. with `ONION_ANIMATED=1` also writes `{dir}_onion.gif`, where the
+/// trail builds up over time
+fn make_onion_skin() -> anyhow::Result<()> {
+    let dir = env_var("IMAGE_DIR");
+    let config = OnionConfig::from_env()?;
+    let positions = positions_from_env(&dir)?;
+    let mut first = None;
+    let mut last = None;
+    let mut sampled = vec![];
+    for (n, (i, p)) in get_images(ImageSelectionConfig::from_env()?)?.enumerate() {
+        if n % config.stride == 0 {
+            match locate_link(&positions, &i, &p) {
+                Some(pos) => sampled.push((i.clone(), pos)),
+                None => println!("unable to find link in {p:?}"),
+            }
+        }
+        if first.is_none() {
+            first = Some(i.clone());
+        }
+        last = Some(i);
+    }
+    let background = match config.background {
+        Background::First => first,
+        Background::Last => last,
+    }
+    .ok_or_else(|| anyhow!("No images selected"))?;
+    let frames: Vec<(&DynamicImage, (i32, i32))> = sampled.iter().map(|(i, p)| (i, *p)).collect();
+
+    fs::create_dir_all("images/out")?;
+    let out_path = format!("images/out/{dir}_onion.png");
+    println!("Writing file to {out_path}");
+    onion_skin(&frames, &background, config.fade, config.min_opacity).save(&out_path)?;
+
+    if config.animated {
+        let out_path = format!("images/out/{dir}_onion.gif");
+        println!("Writing file to {out_path}");
+        let images = (1..=frames.len()).map(|n| {
+            DynamicImage::ImageRgba8(onion_skin(
+                &frames[..n],
+                &background,
+                config.fade,
+                config.min_opacity,
+            ))
+        });
+        write_gif(images, File::create(&out_path)?, Fps30)?;
+    }
+    Ok(())
+}
+
+/// lays the selected frames out in a grid in one png, `images/out/{dir}_sheet.png`, with each
+/// frame's number underneath. frames are cropped if `CROP_X` is set and upscaled if
+/// `SCALE_FILTER` is
+fn make_contact_sheet() -> anyhow::Result<()> {
+    let dir = env_var("IMAGE_DIR");
+    let layout = SheetLayout::from_env()?;
+    let cropper = if env_var_opt("CROP_X").is_some() {
+        Some(Cropper::new_from_env()?)
+    } else {
+        None
+    };
+    let scale_filter = scale_filter_from_env()?;
+    let font = bundled_font(env_var_opt("SHEET_FONT").as_deref().unwrap_or("arial_bold"))?;
+    let frames: Vec<(DynamicImage, Option<u32>)> = get_images(ImageSelectionConfig::from_env()?)?
+        .map(|(i, p)| {
+            let i = match &cropper {
+                Some(c) => c.crop_around_middle(&i),
+                None => i,
+            };
+            let i = match scale_filter {
+                Some(f) => upscale(&i, f),
+                None => i,
+            };
+            (i, number_from_pathbuf(&p))
+        })
+        .collect();
+    if frames.is_empty() {
+        return Err(anyhow!("No images selected"));
+    }
+    fs::create_dir_all("images/out")?;
+    let out_path = format!("images/out/{dir}_sheet.png");
+    println!("Writing {} frames to {out_path}", frames.len());
+    contact_sheet(&frames, &layout, &font)?.save(&out_path)?;
+    Ok(())
+}
+
+/// crops a `width` x `height` box centred on (`middle_x`, `middle_y`), shifted as needed to stay
+/// inside the image
+fn crop_centred(
+    i: &DynamicImage,
+    middle_x: i32,
+    middle_y: i32,
+    width: i32,
+    height: i32,
+) -> DynamicImage {
+    let (topleft_x, topleft_y) = centred_origin(i, middle_x, middle_y, width, height);
+    i.crop_imm(topleft_x, topleft_y, width as u32, height as u32)
+}
+
+/// the top left of a `width` x `height` box centred on (`middle_x`, `middle_y`), shifted as
+/// needed to stay inside the image
+fn centred_origin(
+    i: &DynamicImage,
+    middle_x: i32,
+    middle_y: i32,
+    width: i32,
+    height: i32,
+) -> (u32, u32) {
+    let image_width = i.width() as i32;
+    let image_height = i.height() as i32;
+    let mut topleft_x = (middle_x - (width / 2)).clamp(0, image_width);
+    if topleft_x + width > image_width {
+        topleft_x = (image_width - width).max(0);
+    }
+    let mut topleft_y = (middle_y - (height / 2)).clamp(0, image_height);
+    if topleft_y + height > image_height {
+        topleft_y = (image_height - height).max(0);
+    }
+    (topleft_x as u32, topleft_y as u32)
+}
+
 fn make_gif_with_crop() -> anyhow::Result<()> {
     let dir = env_var("IMAGE_DIR");
     let out_path = format!("images/out/{dir}.gif");
@@ -174,48 +634,120 @@ fn make_gif_with_crop() -> anyhow::Result<()> {
 }
 
 fn to_gif(cropper: &Cropper, output_fn: &str) -> anyhow::Result<()> {
-    let out_width = env_var("OUT_WIDTH").parse()?;
-    let out_height = env_var("OUT_HEIGHT").parse()?;
-    let skip_alternating = if env_var("SKIP_ALTERNATING").parse::<u32>().unwrap() == 1 {
-        true
+    let scale_filter = scale_filter_from_env()?;
+    let aspect = AspectCorrection::from_env()?;
+    // only needed when there's no scale filter or aspect correction
+    let out_size = if scale_filter.is_none() && aspect.is_none() {
+        (
+            env_var("OUT_WIDTH").parse()?,
+            env_var("OUT_HEIGHT").parse()?,
+        )
     } else {
-        false
+        (0, 0)
     };
-    let images = get_images(ImageSelectionConfig::from_env()?)?
-        .map(|(i, _)| cropper.crop_around_middle(&i))
-        .map(|i| i.resize(out_width, out_height, FilterType::Nearest));
+    let isc = ImageSelectionConfig::from_env()?;
+    let crt = CrtFilter::from_env(output_scale_factor(scale_filter, aspect.as_ref()))?;
+    let manifest = CaptureManifest::load(&env_var("IMAGE_DIR"))?;
+    let fps = frame_duration_from_env(manifest.as_ref(), isc.skip_alternating)?;
+    let images = Overlays::from_env(manifest.as_ref())?.apply(
+        get_images(isc)?
+            .map(|(i, p)| (cropper.crop_around_middle(&i), p))
+            .map(|(i, p)| {
+                let i = scale_for_output(i, scale_filter, aspect.as_ref(), out_size);
+                (apply_crt(i, crt.as_ref()), p)
+            }),
+    );
 
-    let f = File::create(output_fn).expect(&format!("Failed to create file {output_fn}"));
-    write_gif(images, f, if skip_alternating { Fps30 } else { Fps60 })?;
+    let f =
+        File::create(output_fn).map_err(|e| anyhow!("Failed to create file {output_fn}: {e}"))?;
+    write_gif(images, f, fps)?;
     Ok(())
 }
 
-enum FrameDuration {
-    Fps30,
-    Fps60,
+/// `SCALE_FILTER`, e.g. `integer:3` or `scale2x`. see `ScaleFilter`
+fn scale_filter_from_env() -> anyhow::Result<Option<ScaleFilter>> {
+    env_var_opt("SCALE_FILTER").map(|f| f.parse()).transpose()
 }
-/// just writes the gif given the input images
-fn write_gif<W: Write, I: Iterator<Item = DynamicImage>>(
-    images: I,
-    mut out: W,
-    fd: FrameDuration,
-) -> anyhow::Result<()> {
-    let mut ge = GifEncoder::new_with_speed(&mut out, 1);
-    ge.set_repeat(Repeat::Infinite)?;
 
-    let (short_ms, long_ms) = match fd {
-        FrameDuration::Fps30 => (20, 40),
-        FrameDuration::Fps60 => (10, 20),
-    };
-    println!("{short_ms}, {long_ms}");
-    let short_delay = Delay::from_saturating_duration(Duration::from_millis(short_ms));
-    let long_delay = Delay::from_saturating_duration(Duration::from_millis(long_ms));
-
-    ge.encode_frames(images.enumerate().map(|(c, i)| {
-        let delay = if c % 2 == 0 { short_delay } else { long_delay };
-        Frame::from_parts(i.into_rgba8(), 0, 0, delay)
-    }))?;
-    Ok(())
+/// stretching frames to a tv's pixel aspect ratio. set with `ASPECT` (`8:7` or `4:3`)
+struct AspectCorrection {
+    aspect: PixelAspect,
+    /// integer upscale to do first, when there's no `SCALE_FILTER`. from `ASPECT_SCALE`
+    scale: u32,
+}
+
+impl AspectCorrection {
+    fn from_env() -> anyhow::Result<Option<Self>> {
+        let Some(aspect) = env_var_opt("ASPECT") else {
+            return Ok(None);
+        };
+        let scale = env_var_opt("ASPECT_SCALE")
+            .map(|s| s.parse())
+            .transpose()?
+            .unwrap_or(4);
+        Ok(Some(Self {
+            aspect: aspect.parse()?,
+            scale,
+        }))
+    }
+
+    /// upscales (with `filter` if given, by `scale` otherwise) and then stretches
+    fn apply(&self, i: &DynamicImage, filter: Option<ScaleFilter>) -> DynamicImage {
+        let upscaled = upscale(i, filter.unwrap_or(ScaleFilter::Integer(self.scale)));
+        correct_aspect(&upscaled, self.aspect)
+    }
+}
+
+/// upscales with `filter` and/or corrects the aspect ratio if either is set, otherwise does a
+/// plain nearest neighbour resize to fit `fallback_size`
+fn scale_for_output(
+    i: DynamicImage,
+    filter: Option<ScaleFilter>,
+    aspect: Option<&AspectCorrection>,
+    fallback_size: (u32, u32),
+) -> DynamicImage {
+    match (filter, aspect) {
+        (_, Some(a)) => a.apply(&i, filter),
+        (Some(f), None) => upscale(&i, f),
+        (None, None) => i.resize(fallback_size.0, fallback_size.1, FilterType::Nearest),
+    }
+}
+
+/// how many output pixels each source pixel became in `scale_for_output`. 1 for the fallback
+/// resize, since that isn't a whole number
+fn output_scale_factor(filter: Option<ScaleFilter>, aspect: Option<&AspectCorrection>) -> u32 {
+    match (filter, aspect) {
+        (Some(f), _) => f.factor(),
+        (None, Some(a)) => a.scale,
+        (None, None) => 1,
+    }
+}
+
+fn apply_crt(i: DynamicImage, crt: Option<&CrtFilter>) -> DynamicImage {
+    match crt {
+        Some(c) => c.apply(&i),
+        None => i,
+    }
+}
+
+/// `FPS` (30 or 60) if it's set, otherwise whatever's closest to the rate of the frames that are
+/// left once `skip_alternating` has dropped every other one
+fn frame_duration_from_env(
+    manifest: Option<&CaptureManifest>,
+    skip_alternating: bool,
+) -> anyhow::Result<FrameDuration> {
+    if let Some(fps) = env_var_opt("FPS") {
+        return Ok(if fps.parse::<u32>()? == 30 {
+            Fps30
+        } else {
+            Fps60
+        });
+    }
+    Ok(match manifest {
+        Some(m) => m.frame_duration(skip_alternating),
+        None if skip_alternating => Fps30,
+        None => Fps60,
+    })
 }
 
 struct Cropper {
@@ -0,0 +1,102 @@
+use image_misc::gif::FrameDuration;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+fn one() -> u32 {
+    1
+}
+
+fn png() -> String {
+    "png".to_string()
+}
+
+/// `manifest.json` as written next to the frames by `bizhawk/screenshot-every-frame.lua`
+#[derive(serde::Deserialize, Debug, Clone)]
+pub struct CaptureManifest {
+    pub rom_name: String,
+    pub system: String,
+    /// frame rate of the emulated system, not of the saved frames
+    pub fps: f64,
+    /// only every nth frame was saved
+    #[serde(default = "one")]
+    pub every_nth: u32,
+    /// emulator frame count when capturing started. frame file names and the logs count frames
+    /// the same way, so this is also the number of the first frame
+    pub capture_start_frame: u64,
+    /// how many digits the frame number in each file name is padded to
+    #[serde(default)]
+    pub digits: Option<usize>,
+    #[serde(default = "png")]
+    pub extension: String,
+    /// input log file name, relative to the capture directory
+    #[serde(default)]
+    pub input_log: Option<String>,
+    /// link's logged positions, relative to the capture directory
+    #[serde(default)]
+    pub positions_log: Option<String>,
+    #[serde(skip)]
+    pub dir: PathBuf,
+}
+
+impl CaptureManifest {
+    /// the manifest in `images/{dir}`, if there is one
+    pub fn load(dir: &str) -> anyhow::Result<Option<Self>> {
+        let dir = PathBuf::from(format!("images/{dir}"));
+        let path = dir.join("manifest.json");
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut manifest: Self = serde_json::from_str(&read_to_string(&path)?)
+            .map_err(|e| anyhow::anyhow!("Error parsing {path:?}: {e}"))?;
+        manifest.dir = dir;
+        Ok(Some(manifest))
+    }
+
+    /// whether `p` is one of the captured frames, rather than the manifest, a log or some other
+    /// numbered file that was put in the directory
+    pub fn is_frame(&self, p: &Path) -> bool {
+        self.frame_number(p).is_some()
+    }
+
+    /// the emulator frame `p` was captured on, if it's named like a captured frame
+    pub fn frame_number(&self, p: &Path) -> Option<u64> {
+        if p.extension().and_then(|e| e.to_str()) != Some(self.extension.as_str()) {
+            return None;
+        }
+        let stem = p.file_stem()?.to_str()?;
+        // numbers too big for the padding are written out in full
+        if self.digits.is_some_and(|d| stem.len() < d) || !stem.bytes().all(|b| b.is_ascii_digit())
+        {
+            return None;
+        }
+        stem.parse().ok()
+    }
+
+    /// frame rate of the saved frames
+    pub fn effective_fps(&self) -> f64 {
+        self.fps / self.every_nth.max(1) as f64
+    }
+
+    /// the closest gif timing to the saved frame rate, after `SKIP_ALTERNATING` drops every other
+    /// frame if `skip_alternating`
+    pub fn frame_duration(&self, skip_alternating: bool) -> FrameDuration {
+        let fps = if skip_alternating {
+            self.effective_fps() / 2.0
+        } else {
+            self.effective_fps()
+        };
+        if fps > 45.0 {
+            FrameDuration::Fps60
+        } else {
+            FrameDuration::Fps30
+        }
+    }
+
+    pub fn input_log_path(&self) -> Option<PathBuf> {
+        self.input_log.as_ref().map(|l| self.dir.join(l))
+    }
+
+    pub fn positions_log_path(&self) -> Option<PathBuf> {
+        self.positions_log.as_ref().map(|l| self.dir.join(l))
+    }
+}
@@ -0,0 +1,134 @@
+use crate::env_var_opt;
+use anyhow::anyhow;
+use image::{DynamicImage, GenericImageView, Pixel, Rgba, RgbaImage};
+
+/// link's sprite box, measured from the top left corner `find_link` returns
+pub const LINK_WIDTH: i32 = 16;
+pub const LINK_HEIGHT: i32 = 24;
+
+/// how opacity drops off for older positions
+#[derive(Debug, Clone, Copy)]
+pub enum FadeCurve {
+    Linear,
+    /// stays solid for longer, then drops off quickly near the oldest positions
+    Quadratic,
+    /// every position fully opaque
+    None,
+}
+
+impl FadeCurve {
+    /// `age` is 0 for the newest position and 1 for the oldest
+    pub fn opacity(&self, age: f64, min_opacity: f64) -> f64 {
+        let fade = match self {
+            FadeCurve::Linear => age,
+            FadeCurve::Quadratic => age * age,
+            FadeCurve::None => 0.0,
+        };
+        1.0 - fade * (1.0 - min_opacity)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Background {
+    First,
+    Last,
+}
+
+pub struct OnionConfig {
+    /// only every `stride`th frame is drawn
+    pub stride: usize,
+    pub fade: FadeCurve,
+    /// opacity of the oldest position
+    pub min_opacity: f64,
+    pub background: Background,
+    /// also write a gif where the trail builds up one position at a time
+    pub animated: bool,
+}
+
+impl OnionConfig {
+    pub fn from_env() -> anyhow::Result<Self> {
+        let stride = env_var_opt("ONION_STRIDE")
+            .map(|s| s.parse())
+            .transpose()?
+            .unwrap_or(4);
+        let fade = match env_var_opt("ONION_FADE").as_deref() {
+            None | Some("linear") => FadeCurve::Linear,
+            Some("quadratic") => FadeCurve::Quadratic,
+            Some("none") => FadeCurve::None,
+            Some(other) => return Err(anyhow!("Unknown ONION_FADE {other}")),
+        };
+        let min_opacity = env_var_opt("ONION_MIN_OPACITY")
+            .map(|s| s.parse())
+            .transpose()?
+            .unwrap_or(0.2);
+        let background = match env_var_opt("ONION_BACKGROUND").as_deref() {
+            None | Some("last") => Background::Last,
+            Some("first") => Background::First,
+            Some(other) => return Err(anyhow!("Unknown ONION_BACKGROUND {other}")),
+        };
+        let animated = env_var_opt("ONION_ANIMATED").as_deref() == Some("1");
+        Ok(Self {
+            stride: stride.max(1),
+            fade,
+            min_opacity,
+            background,
+            animated,
+        })
+    }
+}
+
+fn blend(under: Rgba<u8>, over: Rgba<u8>, opacity: f64) -> Rgba<u8> {
+    let mut out = under;
+    for c in 0..3 {
+        out.0[c] = (under.0[c] as f64 * (1.0 - opacity) + over.0[c] as f64 * opacity).round() as u8;
+    }
+    out
+}
+
+/// draws link from each frame onto `background`, oldest first so the newest ends up on top.
+/// `positions` are link's top left in each frame, oldest first.
+///
+/// only pixels in link's box that differ from the background are copied, so this works best
+/// when the screen doesn't scroll over the course of the frames
+pub fn onion_skin(
+    frames: &[(&DynamicImage, (i32, i32))],
+    background: &DynamicImage,
+    fade: FadeCurve,
+    min_opacity: f64,
+) -> RgbaImage {
+    let mut out = background.to_rgba8();
+    let (width, height) = background.dimensions();
+    let count = frames.len();
+    for (n, (frame, (link_x, link_y))) in frames.iter().enumerate() {
+        let age = if count > 1 {
+            (count - 1 - n) as f64 / (count - 1) as f64
+        } else {
+            0.0
+        };
+        let opacity = fade.opacity(age, min_opacity);
+        for y in *link_y..link_y + LINK_HEIGHT {
+            for x in *link_x..link_x + LINK_WIDTH {
+                if x < 0 || y < 0 || x as u32 >= width || y as u32 >= height {
+                    continue;
+                }
+                let (x, y) = (x as u32, y as u32);
+                if x >= frame.width() || y >= frame.height() {
+                    continue;
+                }
+                let px = frame.get_pixel(x, y);
+                let bg = background.get_pixel(x, y);
+                let differs = px
+                    .to_rgb()
+                    .0
+                    .iter()
+                    .zip(bg.to_rgb().0.iter())
+                    .any(|(a, b)| a.abs_diff(*b) > 16);
+                if differs {
+                    let under = *out.get_pixel(x, y);
+                    out.put_pixel(x, y, blend(under, px, opacity));
+                }
+            }
+        }
+    }
+    out
+}
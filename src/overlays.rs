@@ -0,0 +1,51 @@
+use crate::annotate::Annotator;
+use crate::frame_stamp::FrameStamper;
+use crate::input_display::InputDisplay;
+use crate::manifest::CaptureManifest;
+use crate::number_from_pathbuf;
+use image::DynamicImage;
+use std::path::PathBuf;
+
+/// everything the gif modes can draw over the (already resized) output frames
+pub struct Overlays {
+    annotator: Option<Annotator<'static>>,
+    stamper: Option<FrameStamper>,
+    input_display: Option<InputDisplay>,
+}
+
+impl Overlays {
+    /// the capture manifest, if there is one, fills in defaults like the frame rate and input log
+    pub fn from_env(manifest: Option<&CaptureManifest>) -> anyhow::Result<Self> {
+        Ok(Self {
+            annotator: Annotator::from_env()?,
+            stamper: FrameStamper::from_env(manifest)?,
+            input_display: InputDisplay::from_env(manifest)?,
+        })
+    }
+
+    /// `images` are the output frames along with the path of the frame they came from
+    pub fn apply<I: Iterator<Item = (DynamicImage, PathBuf)>>(
+        self,
+        images: I,
+    ) -> impl Iterator<Item = DynamicImage> {
+        let mut start_frame = None;
+        images.enumerate().map(move |(n, (i, p))| {
+            let frame = number_from_pathbuf(&p);
+            if start_frame.is_none() {
+                start_frame = frame;
+            }
+            let i = match &self.annotator {
+                Some(a) => a.annotate(n, i),
+                None => i,
+            };
+            let i = match &self.input_display {
+                Some(d) => d.draw(i, frame),
+                None => i,
+            };
+            match &self.stamper {
+                Some(s) => s.stamp(i, frame, start_frame),
+                None => i,
+            }
+        })
+    }
+}
@@ -0,0 +1,443 @@
+//! upscaling for pixel art. unlike `DynamicImage::resize`, every filter here scales by a whole
+//! number, so every source pixel ends up the same size
+
+use anyhow::anyhow;
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::Deserialize;
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+/// in json, the same strings `from_str` takes
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(try_from = "String")]
+pub enum ScaleFilter {
+    /// plain nearest neighbour by a whole number
+    Integer(u32),
+    /// AdvMAME2x
+    Scale2x,
+    /// AdvMAME3x
+    Scale3x,
+    /// Scale2x twice
+    Scale4x,
+    /// Eric's Pixel Expansion. very close to Scale2x, but keeps the original pixel wherever three
+    /// or more neighbours agree
+    Epx,
+    /// Maxim Stepin's hq2x. neighbours are compared in YUV with some tolerance and edges are
+    /// blended, so the output has colours the input didn't
+    Hq2x,
+}
+
+impl ScaleFilter {
+    /// how many times bigger the output is
+    pub fn factor(&self) -> u32 {
+        match self {
+            ScaleFilter::Integer(f) => *f,
+            ScaleFilter::Scale2x | ScaleFilter::Epx | ScaleFilter::Hq2x => 2,
+            ScaleFilter::Scale3x => 3,
+            ScaleFilter::Scale4x => 4,
+        }
+    }
+}
+
+impl FromStr for ScaleFilter {
+    type Err = anyhow::Error;
+
+    /// `integer:N`, `scale2x`, `scale3x`, `scale4x`, `epx` or `hq2x`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(f) = s.strip_prefix("integer:") {
+            let f: u32 = f.parse()?;
+            if f == 0 {
+                return Err(anyhow!("Integer scale factor must be at least 1"));
+            }
+            return Ok(ScaleFilter::Integer(f));
+        }
+        match s {
+            "scale2x" => Ok(ScaleFilter::Scale2x),
+            "scale3x" => Ok(ScaleFilter::Scale3x),
+            "scale4x" => Ok(ScaleFilter::Scale4x),
+            "epx" => Ok(ScaleFilter::Epx),
+            "hq2x" => Ok(ScaleFilter::Hq2x),
+            other => Err(anyhow!("Unknown scale filter {other}")),
+        }
+    }
+}
+
+impl TryFrom<String> for ScaleFilter {
+    type Error = anyhow::Error;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+pub fn upscale(image: &DynamicImage, filter: ScaleFilter) -> DynamicImage {
+    let image = image.to_rgba8();
+    DynamicImage::ImageRgba8(match filter {
+        ScaleFilter::Integer(f) => integer_scale(&image, f),
+        ScaleFilter::Scale2x => scale2x(&image),
+        ScaleFilter::Scale3x => scale3x(&image),
+        ScaleFilter::Scale4x => scale2x(&scale2x(&image)),
+        ScaleFilter::Epx => epx(&image),
+        ScaleFilter::Hq2x => hq2x(&image),
+    })
+}
+
+/// the biggest whole number factor that keeps `width` x `height` within `max_width` x
+/// `max_height`. never less than 1
+pub fn integer_factor_to_fit(width: u32, height: u32, max_width: u32, max_height: u32) -> u32 {
+    (max_width / width.max(1))
+        .min(max_height / height.max(1))
+        .max(1)
+}
+
+pub fn integer_scale(image: &RgbaImage, factor: u32) -> RgbaImage {
+    RgbaImage::from_fn(image.width() * factor, image.height() * factor, |x, y| {
+        *image.get_pixel(x / factor, y / factor)
+    })
+}
+
+/// the pixel at (x + dx, y + dy), with coordinates past the edge clamped to the edge
+fn neighbour(image: &RgbaImage, x: u32, y: u32, dx: i32, dy: i32) -> Rgba<u8> {
+    let nx = (x as i32 + dx).clamp(0, image.width() as i32 - 1) as u32;
+    let ny = (y as i32 + dy).clamp(0, image.height() as i32 - 1) as u32;
+    *image.get_pixel(nx, ny)
+}
+
+/// runs `expand` over every pixel and writes the `factor` x `factor` block it returns, row by row
+fn expand_each<F: Fn(&RgbaImage, u32, u32) -> Vec<Rgba<u8>>>(
+    image: &RgbaImage,
+    factor: u32,
+    expand: F,
+) -> RgbaImage {
+    let mut out = RgbaImage::new(image.width() * factor, image.height() * factor);
+    for y in 0..image.height() {
+        for x in 0..image.width() {
+            for (n, px) in expand(image, x, y).into_iter().enumerate() {
+                let n = n as u32;
+                out.put_pixel(x * factor + n % factor, y * factor + n / factor, px);
+            }
+        }
+    }
+    out
+}
+
+pub fn scale2x(image: &RgbaImage) -> RgbaImage {
+    expand_each(image, 2, |image, x, y| {
+        let p = *image.get_pixel(x, y);
+        let a = neighbour(image, x, y, 0, -1);
+        let b = neighbour(image, x, y, 1, 0);
+        let c = neighbour(image, x, y, -1, 0);
+        let d = neighbour(image, x, y, 0, 1);
+        if c != b && a != d {
+            vec![
+                if c == a { a } else { p },
+                if a == b { b } else { p },
+                if c == d { c } else { p },
+                if d == b { d } else { p },
+            ]
+        } else {
+            vec![p; 4]
+        }
+    })
+}
+
+pub fn epx(image: &RgbaImage) -> RgbaImage {
+    expand_each(image, 2, |image, x, y| {
+        let p = *image.get_pixel(x, y);
+        let a = neighbour(image, x, y, 0, -1);
+        let b = neighbour(image, x, y, 1, 0);
+        let c = neighbour(image, x, y, -1, 0);
+        let d = neighbour(image, x, y, 0, 1);
+        let neighbours = [a, b, c, d];
+        let three_agree = neighbours
+            .iter()
+            .any(|n| neighbours.iter().filter(|m| *m == n).count() >= 3);
+        if three_agree {
+            return vec![p; 4];
+        }
+        vec![
+            if c == a { a } else { p },
+            if a == b { b } else { p },
+            if d == c { c } else { p },
+            if b == d { d } else { p },
+        ]
+    })
+}
+
+pub fn scale3x(image: &RgbaImage) -> RgbaImage {
+    expand_each(image, 3, |image, x, y| {
+        let n = |dx, dy| neighbour(image, x, y, dx, dy);
+        let (a, b, c) = (n(-1, -1), n(0, -1), n(1, -1));
+        let (d, e, f) = (n(-1, 0), n(0, 0), n(1, 0));
+        let (g, h, i) = (n(-1, 1), n(0, 1), n(1, 1));
+        if b == h || d == f {
+            return vec![e; 9];
+        }
+        vec![
+            if d == b { d } else { e },
+            if (d == b && e != c) || (b == f && e != a) {
+                b
+            } else {
+                e
+            },
+            if b == f { f } else { e },
+            if (d == b && e != g) || (d == h && e != a) {
+                d
+            } else {
+                e
+            },
+            e,
+            if (b == f && e != i) || (h == f && e != c) {
+                f
+            } else {
+                e
+            },
+            if d == h { d } else { e },
+            if (d == h && e != i) || (h == f && e != g) {
+                h
+            } else {
+                e
+            },
+            if h == f { f } else { e },
+        ]
+    })
+}
+
+/// hq2x's YUV, which isn't quite the usual one
+fn yuv(px: Rgba<u8>) -> (i32, i32, i32) {
+    let [r, g, b, _] = px.0.map(|c| c as i32);
+    ((r + g + b) >> 2, (r - b) >> 2, (2 * g - r - b) >> 3)
+}
+
+/// hq2x's idea of two colours being different. pixels that are see through to different
+/// amounts always are
+fn differs(a: Rgba<u8>, b: Rgba<u8>) -> bool {
+    if a == b {
+        return false;
+    }
+    if a.0[3] != b.0[3] {
+        return true;
+    }
+    let (ya, ua, va) = yuv(a);
+    let (yb, ub, vb) = yuv(b);
+    (ya - yb).abs() > 48 || (ua - ub).abs() > 7 || (va - vb).abs() > 6
+}
+
+/// the colours mixed in proportion to their weights, which add up to a power of two
+fn mix(parts: &[(Rgba<u8>, u32)]) -> Rgba<u8> {
+    let total: u32 = parts.iter().map(|(_, w)| w).sum();
+    let mut out = parts[0].0;
+    for c in 0..4 {
+        let sum: u32 = parts.iter().map(|(px, w)| px.0[c] as u32 * w).sum();
+        out.0[c] = (sum / total) as u8;
+    }
+    out
+}
+
+/// the top left quarter of an hq2x pixel. the other quarters are the same with the
+/// neighbourhood mirrored, so `n` is always laid out as if this were the top left:
+///
+/// ```text
+/// 0 1 2
+/// 3 4 5
+/// 6 7 8
+/// ```
+fn hq2x_corner(n: [Rgba<u8>; 9]) -> Rgba<u8> {
+    let e = n[4];
+    let (corner, up, left) = (n[0], n[1], n[3]);
+    let d = |i: usize| differs(n[i], e);
+    match (d(1), d(3)) {
+        (false, false) => mix(&[(e, 2), (left, 1), (up, 1)]),
+        // an edge along the top
+        (true, false) => {
+            if !d(0) {
+                mix(&[(e, 2), (corner, 1), (left, 1)])
+            } else if d(5) && !differs(up, n[5]) {
+                mix(&[(e, 5), (up, 2), (left, 1)])
+            } else {
+                mix(&[(e, 3), (left, 1)])
+            }
+        }
+        // an edge down the left
+        (false, true) => {
+            if !d(0) {
+                mix(&[(e, 2), (corner, 1), (up, 1)])
+            } else if d(7) && !differs(left, n[7]) {
+                mix(&[(e, 5), (left, 2), (up, 1)])
+            } else {
+                mix(&[(e, 3), (up, 1)])
+            }
+        }
+        // both, so this corner is either a corner of something or on a diagonal
+        (true, true) => {
+            if differs(left, up) {
+                if d(0) {
+                    e
+                } else {
+                    mix(&[(e, 3), (corner, 1)])
+                }
+            } else if !d(0) {
+                mix(&[(e, 6), (left, 1), (up, 1)])
+            } else {
+                match (d(2), d(6)) {
+                    (true, true) => mix(&[(e, 14), (left, 1), (up, 1)]),
+                    (true, false) | (false, true) => mix(&[(e, 2), (left, 3), (up, 3)]),
+                    (false, false) => mix(&[(e, 2), (left, 1), (up, 1)]),
+                }
+            }
+        }
+    }
+}
+
+pub fn hq2x(image: &RgbaImage) -> RgbaImage {
+    expand_each(image, 2, |image, x, y| {
+        let n = |dx, dy| neighbour(image, x, y, dx, dy);
+        let around = [
+            n(-1, -1),
+            n(0, -1),
+            n(1, -1),
+            n(-1, 0),
+            n(0, 0),
+            n(1, 0),
+            n(-1, 1),
+            n(0, 1),
+            n(1, 1),
+        ];
+        // the index in `around` that lands on `i` once flipped sideways, upside down or both
+        let flip_x = |i: usize| i / 3 * 3 + 2 - i % 3;
+        let flip_y = |i: usize| (2 - i / 3) * 3 + i % 3;
+        let flipped =
+            |f: &dyn Fn(usize) -> usize| hq2x_corner(std::array::from_fn(|i| around[f(i)]));
+        vec![
+            flipped(&|i| i),
+            flipped(&flip_x),
+            flipped(&flip_y),
+            flipped(&|i| flip_x(flip_y(i))),
+        ]
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+    const INK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+
+    /// `#` for ink, anything else for clear
+    fn picture(rows: &[&str]) -> RgbaImage {
+        RgbaImage::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| {
+            if rows[y as usize].as_bytes()[x as usize] == b'#' {
+                INK
+            } else {
+                CLEAR
+            }
+        })
+    }
+
+    fn rows(image: &RgbaImage) -> Vec<String> {
+        (0..image.height())
+            .map(|y| {
+                (0..image.width())
+                    .map(|x| match *image.get_pixel(x, y) {
+                        INK => '#',
+                        CLEAR => '.',
+                        _ => '?',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn scale2x_smooths_a_diagonal() {
+        let diagonal = picture(&["#..", ".#.", "..#"]);
+        assert_eq!(
+            rows(&scale2x(&diagonal)),
+            ["##....", "#.#...", ".###..", "..###.", "...#.#", "....##"]
+        );
+    }
+
+    #[test]
+    fn scale3x_rounds_off_a_corner() {
+        let corner = picture(&["##", "#."]);
+        assert_eq!(
+            rows(&scale3x(&corner)),
+            ["######", "######", "######", "#####.", "####..", "###..."]
+        );
+    }
+
+    #[test]
+    fn epx_rounds_off_a_corner() {
+        let corner = picture(&["##", "#."]);
+        assert_eq!(rows(&epx(&corner)), ["####", "####", "###.", "##.."]);
+    }
+
+    #[test]
+    fn scale4x_is_scale2x_twice() {
+        let corner = picture(&["##", "#."]);
+        let once = upscale(
+            &DynamicImage::ImageRgba8(corner.clone()),
+            ScaleFilter::Scale2x,
+        );
+        assert_eq!(
+            upscale(&DynamicImage::ImageRgba8(corner), ScaleFilter::Scale4x),
+            upscale(&once, ScaleFilter::Scale2x)
+        );
+    }
+
+    #[test]
+    fn flat_colour_stays_flat() {
+        let flat = RgbaImage::from_pixel(3, 2, Rgba([12, 200, 90, 255]));
+        for filter in [
+            ScaleFilter::Integer(3),
+            ScaleFilter::Scale2x,
+            ScaleFilter::Scale3x,
+            ScaleFilter::Epx,
+            ScaleFilter::Hq2x,
+        ] {
+            let out = upscale(&DynamicImage::ImageRgba8(flat.clone()), filter).to_rgba8();
+            assert_eq!(out.dimensions(), (3 * filter.factor(), 2 * filter.factor()));
+            assert!(
+                out.pixels().all(|p| p == flat.get_pixel(0, 0)),
+                "{:?}",
+                filter
+            );
+        }
+    }
+
+    #[test]
+    fn hq2x_blends_edges_the_same_way_round_every_corner() {
+        let white = Rgba([255, 255, 255, 255]);
+        let corner = RgbaImage::from_fn(3, 3, |x, y| if x + y < 3 { INK } else { white });
+        let out = hq2x(&corner);
+        // a diagonal edge comes out with greys on it
+        assert!(out
+            .pixels()
+            .any(|p| p.0[0] > 0 && p.0[0] < 255 && p.0[0] == p.0[1]));
+        // and flipping the picture along the diagonal flips the output the same way
+        let flipped = RgbaImage::from_fn(3, 3, |x, y| *corner.get_pixel(y, x));
+        let out_flipped = hq2x(&flipped);
+        for (x, y, px) in out.enumerate_pixels() {
+            assert_eq!(px, out_flipped.get_pixel(y, x), "at {x},{y}");
+        }
+        // colours that are close enough count as the same, so a tiny difference isn't an edge
+        let nearly = RgbaImage::from_fn(2, 1, |x, _| Rgba([100 + x as u8, 100, 100, 255]));
+        assert!(!differs(*nearly.get_pixel(0, 0), *nearly.get_pixel(1, 0)));
+    }
+
+    #[test]
+    fn filters_by_name() {
+        assert_eq!(
+            "integer:3".parse::<ScaleFilter>().unwrap(),
+            ScaleFilter::Integer(3)
+        );
+        assert_eq!("hq2x".parse::<ScaleFilter>().unwrap(), ScaleFilter::Hq2x);
+        assert!("integer:0".parse::<ScaleFilter>().is_err());
+        assert!("soft2x".parse::<ScaleFilter>().is_err());
+        assert_eq!(
+            serde_json::from_str::<ScaleFilter>("\"scale3x\"").unwrap(),
+            ScaleFilter::Scale3x
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use anyhow::anyhow;
+use std::collections::HashMap;
+use std::fs::read_to_string;
+use std::path::Path;
+
+/// link's state on one frame, as logged from ram by `bizhawk/screenshot-every-frame.lua`.
+///
+/// `x`/`y` are link's coordinate relative to the camera, which isn't quite the top left of his
+/// sprite. `PositionCorrection` works out how far it is from what `find_link` returns
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LinkPosition {
+    pub x: i32,
+    pub y: i32,
+    /// 0 up, 2 down, 4 left, 6 right. `None` in logs from before the script wrote it
+    pub direction: Option<u8>,
+    pub indoors: Option<bool>,
+    /// dungeon room when indoors, overworld area otherwise
+    pub room: Option<u16>,
+}
+
+impl LinkPosition {
+    pub fn shifted(self, (x, y): (i32, i32)) -> Self {
+        Self {
+            x: self.x + x,
+            y: self.y + y,
+            ..self
+        }
+    }
+
+    /// whether link is somewhere else than on `previous`. while the screen scrolls to the new
+    /// room the camera and link's coordinate disagree, so those frames are no good for measuring
+    pub fn changed_room(&self, previous: &LinkPosition) -> bool {
+        (self.indoors, self.room) != (previous.indoors, previous.room)
+    }
+}
+
+/// positions by frame number, from a `positions.csv`. logs from older versions of the script,
+/// with only `frame,screen_x,screen_y`, still load
+pub fn parse_positions(contents: &str) -> anyhow::Result<HashMap<u32, LinkPosition>> {
+    let mut out = HashMap::new();
+    for (n, line) in contents.lines().enumerate().skip(1) {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        let fields: Vec<&str> = line.split(',').map(|f| f.trim()).collect();
+        let (frame, x, y, rest) = match fields[..] {
+            [frame, x, y] => (frame, x, y, None),
+            [frame, x, y, direction, indoors, room] => {
+                (frame, x, y, Some((direction, indoors, room)))
+            }
+            _ => return Err(anyhow!("Bad positions line {}: `{line}`", n + 1)),
+        };
+        let (direction, indoors, room) = match rest {
+            Some((direction, indoors, room)) => (
+                Some(direction.parse()?),
+                Some(indoors != "0"),
+                Some(room.parse()?),
+            ),
+            None => (None, None, None),
+        };
+        out.insert(
+            frame.parse()?,
+            LinkPosition {
+                x: x.parse()?,
+                y: y.parse()?,
+                direction,
+                indoors,
+                room,
+            },
+        );
+    }
+    Ok(out)
+}
+
+pub fn load_positions<P: AsRef<Path>>(path: P) -> anyhow::Result<HashMap<u32, LinkPosition>> {
+    let path = path.as_ref();
+    parse_positions(&read_to_string(path)?)
+        .map_err(|e| anyhow!("Error reading positions log {path:?}: {e}"))
+}
+
+/// how far `find_link`'s top left is from the logged position. `find_link` has a different hat
+/// pattern and offset for each way link can face, so each direction gets its own
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PositionCorrection {
+    /// for directions that weren't seen while measuring, and logs without directions
+    pub overall: (i32, i32),
+    pub by_direction: HashMap<u8, (i32, i32)>,
+}
+
+impl PositionCorrection {
+    pub fn fixed(offset: (i32, i32)) -> Self {
+        Self {
+            overall: offset,
+            by_direction: HashMap::new(),
+        }
+    }
+
+    /// the difference that comes up most over frames where both are known, given as
+    /// (`find_link`'s top left, logged position). the most common one rather than the average,
+    /// because `find_link` now and then locks on to the wrong pixels
+    pub fn measure<I: IntoIterator<Item = ((i32, i32), LinkPosition)>>(pairs: I) -> Option<Self> {
+        let mut overall = HashMap::new();
+        let mut by_direction: HashMap<u8, HashMap<(i32, i32), usize>> = HashMap::new();
+        for ((found_x, found_y), logged) in pairs {
+            let offset = (found_x - logged.x, found_y - logged.y);
+            *overall.entry(offset).or_default() += 1;
+            if let Some(d) = logged.direction {
+                *by_direction
+                    .entry(d)
+                    .or_default()
+                    .entry(offset)
+                    .or_default() += 1;
+            }
+        }
+        Some(Self {
+            overall: most_common(overall)?,
+            by_direction: by_direction
+                .into_iter()
+                .filter_map(|(d, counts)| Some((d, most_common(counts)?)))
+                .collect(),
+        })
+    }
+
+    pub fn apply(&self, pos: LinkPosition) -> LinkPosition {
+        let offset = pos
+            .direction
+            .and_then(|d| self.by_direction.get(&d))
+            .unwrap_or(&self.overall);
+        pos.shifted(*offset)
+    }
+}
+
+fn most_common(counts: HashMap<(i32, i32), usize>) -> Option<(i32, i32)> {
+    counts
+        .into_iter()
+        .max_by_key(|(offset, count)| (*count, std::cmp::Reverse(*offset)))
+        .map(|(offset, _)| offset)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn old_and_new_logs_both_load() {
+        let old = parse_positions("frame,screen_x,screen_y\n10,120,96\n").unwrap();
+        assert_eq!(old[&10].direction, None);
+        let new = parse_positions(
+            "frame,screen_x,screen_y,direction,indoors,room\n10,120,96,2,1,260\n11,121,96,6,0,27\n",
+        )
+        .unwrap();
+        assert_eq!(
+            new[&10],
+            LinkPosition {
+                x: 120,
+                y: 96,
+                direction: Some(2),
+                indoors: Some(true),
+                room: Some(260),
+            }
+        );
+        assert!(new[&11].changed_room(&new[&10]));
+        assert!(parse_positions("frame,screen_x,screen_y\n10,120\n").is_err());
+    }
+
+    #[test]
+    fn each_direction_gets_its_own_offset() {
+        let at = |x, y, direction| LinkPosition {
+            x,
+            y,
+            direction: Some(direction),
+            indoors: Some(false),
+            room: Some(0),
+        };
+        let pairs = vec![
+            ((100, 50), at(100, 49, 2)),
+            ((100, 50), at(100, 49, 2)),
+            ((10, 10), at(100, 49, 2)),
+            ((103, 50), at(100, 49, 6)),
+        ];
+        let correction = PositionCorrection::measure(pairs).unwrap();
+        assert_eq!(correction.overall, (0, 1));
+        assert_eq!(correction.apply(at(0, 0, 6)), at(3, 1, 6));
+        assert_eq!(correction.apply(at(0, 0, 0)), at(0, 1, 0));
+    }
+}
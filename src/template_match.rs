@@ -0,0 +1,184 @@
+use image::{DynamicImage, GenericImageView, Pixel, RgbaImage};
+use std::fmt::Display;
+use std::fs::read_dir;
+use std::path::Path;
+
+/// finds a small reference sprite (enemy, item, hud icon...) in a frame.
+///
+/// this is the same idea as `FacingMatcher` in `find_link`, but instead of a hand-written list
+/// of hat pixel offsets the offsets come from a full-colour template image. fully transparent
+/// template pixels are ignored, so whatever is behind the sprite doesn't need to match
+pub struct TemplateMatcher {
+    pub name: String,
+    /// offset from the template's top left and the colour expected there, for every opaque pixel
+    opaque_px: Vec<((i32, i32), [u8; 3])>,
+    width: u32,
+    height: u32,
+    /// how far off each channel is allowed to be. 0 means exact
+    tolerance: u8,
+    /// how many opaque pixels are allowed to not match, for sprites that are partially covered
+    max_mismatches: usize,
+}
+
+impl TemplateMatcher {
+    pub fn new<S: Display>(name: S, template: &DynamicImage) -> anyhow::Result<Self> {
+        let opaque_px: Vec<((i32, i32), [u8; 3])> = template
+            .pixels()
+            .filter(|(_, _, px)| px.0[3] != 0)
+            .map(|(x, y, px)| ((x as i32, y as i32), px.to_rgb().0))
+            .collect();
+        if opaque_px.is_empty() {
+            return Err(anyhow::anyhow!("Template {name} has no opaque pixels"));
+        }
+        Ok(Self {
+            name: name.to_string(),
+            opaque_px,
+            width: template.width(),
+            height: template.height(),
+            tolerance: 0,
+            max_mismatches: 0,
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .unwrap_or("template")
+            .to_string();
+        Self::new(name, &image::open(path)?)
+    }
+
+    pub fn with_tolerance(mut self, tolerance: u8) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    pub fn with_max_mismatches(mut self, max_mismatches: usize) -> Self {
+        self.max_mismatches = max_mismatches;
+        self
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    fn colour_matches(&self, a: [u8; 3], b: [u8; 3]) -> bool {
+        a.iter()
+            .zip(b.iter())
+            .all(|(a, b)| a.abs_diff(*b) <= self.tolerance)
+    }
+
+    fn matches_at(&self, frame: &RgbaImage, start_x: i32, start_y: i32) -> bool {
+        let mut mismatches = 0;
+        for ((offset_x, offset_y), colour) in &self.opaque_px {
+            let px = frame.get_pixel((start_x + offset_x) as u32, (start_y + offset_y) as u32);
+            if !self.colour_matches(px.to_rgb().0, *colour) {
+                mismatches += 1;
+                if mismatches > self.max_mismatches {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// the first position (top left of the template) where the template matches the frame
+    fn find_in(&self, frame: &RgbaImage) -> Option<(i32, i32)> {
+        if self.width > frame.width() || self.height > frame.height() {
+            return None;
+        }
+        for y in 0..=(frame.height() - self.height) as i32 {
+            for x in 0..=(frame.width() - self.width) as i32 {
+                if self.matches_at(frame, x, y) {
+                    return Some((x, y));
+                }
+            }
+        }
+        None
+    }
+}
+
+/// loads every png in `dir` as a template. handy for sprites with several poses, where any of
+/// them counts as a match
+pub fn load_templates<P: AsRef<Path>>(dir: P) -> anyhow::Result<Vec<TemplateMatcher>> {
+    let mut paths = read_dir(dir)?
+        .map(|f| f.map(|f| f.path()))
+        .collect::<Result<Vec<_>, _>>()?;
+    paths.retain(|p| p.extension().and_then(|e| e.to_str()) == Some("png"));
+    paths.sort();
+    paths.iter().map(TemplateMatcher::from_file).collect()
+}
+
+/// tries each matcher in turn and returns the first hit, along with the matcher that found it
+pub fn find_any<'a>(
+    matchers: &'a [TemplateMatcher],
+    image: &DynamicImage,
+) -> Option<(&'a TemplateMatcher, (i32, i32))> {
+    let frame = image.to_rgba8();
+    matchers
+        .iter()
+        .find_map(|m| m.find_in(&frame).map(|pos| (m, pos)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const RED: Rgba<u8> = Rgba([200, 0, 0, 255]);
+    const CLEAR: Rgba<u8> = Rgba([0, 0, 0, 0]);
+
+    /// a 3x2 red bar with a see-through middle on the bottom row
+    fn template() -> TemplateMatcher {
+        let mut t = RgbaImage::from_pixel(3, 2, RED);
+        t.put_pixel(1, 1, CLEAR);
+        TemplateMatcher::new("bar", &DynamicImage::ImageRgba8(t)).unwrap()
+    }
+
+    fn frame_with_bar_at(x: u32, y: u32, colour: Rgba<u8>) -> RgbaImage {
+        let mut frame = RgbaImage::from_pixel(8, 6, Rgba([0, 0, 255, 255]));
+        for (dx, dy) in [(0, 0), (1, 0), (2, 0), (0, 1), (2, 1)] {
+            frame.put_pixel(x + dx, y + dy, colour);
+        }
+        frame
+    }
+
+    #[test]
+    fn transparent_template_pixels_match_anything() {
+        let mut frame = frame_with_bar_at(4, 3, RED);
+        frame.put_pixel(5, 4, Rgba([1, 2, 3, 255]));
+        assert_eq!(template().find_in(&frame), Some((4, 3)));
+    }
+
+    #[test]
+    fn tolerance_allows_close_colours_only() {
+        let frame = frame_with_bar_at(1, 1, Rgba([195, 4, 0, 255]));
+        assert_eq!(template().find_in(&frame), None);
+        assert_eq!(template().with_tolerance(4).find_in(&frame), None);
+        assert_eq!(template().with_tolerance(5).find_in(&frame), Some((1, 1)));
+    }
+
+    #[test]
+    fn max_mismatches_allows_covered_pixels() {
+        let mut frame = frame_with_bar_at(2, 0, RED);
+        frame.put_pixel(2, 0, Rgba([0, 255, 0, 255]));
+        frame.put_pixel(4, 1, Rgba([0, 255, 0, 255]));
+        assert_eq!(template().with_max_mismatches(1).find_in(&frame), None);
+        assert_eq!(
+            template().with_max_mismatches(2).find_in(&frame),
+            Some((2, 0))
+        );
+    }
+
+    #[test]
+    fn templates_bigger_than_the_frame_never_match() {
+        let frame = RgbaImage::from_pixel(2, 2, RED);
+        assert_eq!(template().find_in(&frame), None);
+    }
+}
@@ -0,0 +1,695 @@
+//! reading `.zspr` files, the format alttpr sprites are distributed in, and drawing Link out of
+//! them.
+//!
+//! the header is
+//!
+//! | offset | size | what |
+//! |--------|------|------|
+//! | 0x00 | 4 | `ZSPR` |
+//! | 0x04 | 1 | version |
+//! | 0x05 | 4 | checksum |
+//! | 0x09 | 4 | sprite data offset |
+//! | 0x0D | 2 | sprite data length |
+//! | 0x0F | 4 | palette data offset |
+//! | 0x13 | 2 | palette data length |
+//! | 0x15 | 2 | sprite type, 1 for Link |
+//! | 0x17 | 6 | reserved |
+//! | 0x1D | | display name, utf-16le, nul terminated |
+//! | | | author, utf-16le, nul terminated |
+//! | | | author for the rom credits, ascii, nul terminated |
+//!
+//! the sprite data is 896 snes 4bpp tiles, 16 to a row, which makes a 128x448 sheet. that's
+//! usually talked about as 16x16 cells, rows `A` to `AB` and columns `0` to `7`. the palette
+//! data is 15 bgr555 colours for each of the four mails, then two glove colours
+
+use anyhow::anyhow;
+use image::{Rgba, RgbaImage};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::path::Path;
+use std::str::FromStr;
+
+pub const SHEET_WIDTH: u32 = 128;
+pub const SHEET_HEIGHT: u32 = 448;
+const TILE_BYTES: usize = 32;
+const TILES_PER_ROW: usize = 16;
+const COLOURS_PER_MAIL: usize = 15;
+/// the palette index gloves are drawn with
+const GLOVE_INDEX: usize = 13;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mail {
+    Green,
+    Blue,
+    Red,
+    Bunny,
+}
+
+impl Mail {
+    pub const ALL: [Mail; 4] = [Mail::Green, Mail::Blue, Mail::Red, Mail::Bunny];
+
+    fn index(&self) -> usize {
+        match self {
+            Mail::Green => 0,
+            Mail::Blue => 1,
+            Mail::Red => 2,
+            Mail::Bunny => 3,
+        }
+    }
+}
+
+impl FromStr for Mail {
+    type Err = anyhow::Error;
+
+    /// `green`, `blue`, `red` or `bunny`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "green" => Ok(Mail::Green),
+            "blue" => Ok(Mail::Blue),
+            "red" => Ok(Mail::Red),
+            "bunny" => Ok(Mail::Bunny),
+            other => Err(anyhow!("Unknown mail {other}")),
+        }
+    }
+}
+
+impl std::fmt::Display for Mail {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            Mail::Green => "green",
+            Mail::Blue => "blue",
+            Mail::Red => "red",
+            Mail::Bunny => "bunny",
+        };
+        write!(f, "{s}")
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Gloves {
+    PowerGlove,
+    TitansMitt,
+}
+
+#[derive(Debug, Clone)]
+pub struct Zspr {
+    pub version: u8,
+    pub checksum: u32,
+    pub sprite_type: u16,
+    pub display_name: String,
+    pub author: String,
+    pub author_rom: String,
+    /// raw 4bpp tile data
+    pub graphics: Vec<u8>,
+    /// 15 colours per mail, in `Mail` order. index 0 is always transparent, so isn't stored
+    pub palettes: Vec<[Rgba<u8>; COLOURS_PER_MAIL]>,
+    /// power glove then titan's mitt. older files don't have them
+    pub gloves: Option<[Rgba<u8>; 2]>,
+}
+
+fn u16_at(data: &[u8], at: usize) -> anyhow::Result<u16> {
+    data.get(at..at + 2)
+        .map(|b| u16::from_le_bytes([b[0], b[1]]))
+        .ok_or_else(|| anyhow!("ZSPR ends early at {at:#x}"))
+}
+
+fn u32_at(data: &[u8], at: usize) -> anyhow::Result<u32> {
+    data.get(at..at + 4)
+        .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+        .ok_or_else(|| anyhow!("ZSPR ends early at {at:#x}"))
+}
+
+/// reads a nul terminated utf-16le string starting at `at`, returning it and where it ended
+fn utf16_at(data: &[u8], mut at: usize) -> anyhow::Result<(String, usize)> {
+    let mut units = vec![];
+    loop {
+        let unit = u16_at(data, at)?;
+        at += 2;
+        if unit == 0 {
+            break;
+        }
+        units.push(unit);
+    }
+    Ok((String::from_utf16(&units)?, at))
+}
+
+fn ascii_at(data: &[u8], at: usize) -> anyhow::Result<(String, usize)> {
+    let len = data
+        .get(at..)
+        .and_then(|rest| rest.iter().position(|b| *b == 0))
+        .ok_or_else(|| anyhow!("ZSPR ends in the middle of the rom author"))?;
+    let s = String::from_utf8_lossy(&data[at..at + len]).into_owned();
+    Ok((s, at + len + 1))
+}
+
+pub fn bgr555(colour: u16) -> Rgba<u8> {
+    let channel = |shift: u16| {
+        let c = ((colour >> shift) & 0x1f) as u8;
+        // spread the 5 bits over the whole 8 so 0x1f comes out as 255
+        (c << 3) | (c >> 2)
+    };
+    Rgba([channel(0), channel(5), channel(10), 255])
+}
+
+impl Zspr {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Self::parse(&std::fs::read(path)?)
+    }
+
+    pub fn parse(data: &[u8]) -> anyhow::Result<Self> {
+        if data.get(0..4) != Some(b"ZSPR") {
+            return Err(anyhow!("Not a ZSPR file"));
+        }
+        let version = *data
+            .get(4)
+            .ok_or_else(|| anyhow!("ZSPR header is cut off"))?;
+        let checksum = u32_at(data, 0x05)?;
+        let sprite_offset = u32_at(data, 0x09)? as usize;
+        let sprite_len = u16_at(data, 0x0D)? as usize;
+        let palette_offset = u32_at(data, 0x0F)? as usize;
+        let palette_len = u16_at(data, 0x13)? as usize;
+        let sprite_type = u16_at(data, 0x15)?;
+        let (display_name, at) = utf16_at(data, 0x1D)?;
+        let (author, at) = utf16_at(data, at)?;
+        let (author_rom, _) = ascii_at(data, at)?;
+
+        let graphics = data
+            .get(sprite_offset..sprite_offset + sprite_len)
+            .ok_or_else(|| anyhow!("Sprite data runs past the end of the file"))?
+            .to_vec();
+        if graphics.len() % TILE_BYTES != 0 {
+            return Err(anyhow!(
+                "Sprite data is {} bytes, which isn't a whole number of tiles",
+                graphics.len()
+            ));
+        }
+        let palette_data = data
+            .get(palette_offset..palette_offset + palette_len)
+            .ok_or_else(|| anyhow!("Palette data runs past the end of the file"))?;
+        let colour = |n: usize| u16_at(palette_data, n * 2).map(bgr555);
+
+        let mail_count = (palette_len / (COLOURS_PER_MAIL * 2)).min(Mail::ALL.len());
+        if mail_count == 0 {
+            return Err(anyhow!("ZSPR has no palettes"));
+        }
+        let mut palettes = Vec::with_capacity(mail_count);
+        for mail in 0..mail_count {
+            let mut palette = [Rgba([0, 0, 0, 255]); COLOURS_PER_MAIL];
+            for (n, c) in palette.iter_mut().enumerate() {
+                *c = colour(mail * COLOURS_PER_MAIL + n)?;
+            }
+            palettes.push(palette);
+        }
+        let glove_start = Mail::ALL.len() * COLOURS_PER_MAIL;
+        let gloves = if palette_len >= (glove_start + 2) * 2 {
+            Some([colour(glove_start)?, colour(glove_start + 1)?])
+        } else {
+            None
+        };
+
+        Ok(Self {
+            version,
+            checksum,
+            sprite_type,
+            display_name,
+            author,
+            author_rom,
+            graphics,
+            palettes,
+            gloves,
+        })
+    }
+
+    pub fn tile_count(&self) -> usize {
+        self.graphics.len() / TILE_BYTES
+    }
+
+    /// palette indices for 8x8 tile `n`, row by row
+    pub fn tile(&self, n: usize) -> [[u8; 8]; 8] {
+        let mut out = [[0; 8]; 8];
+        let Some(tile) = self.graphics.get(n * TILE_BYTES..(n + 1) * TILE_BYTES) else {
+            return out;
+        };
+        // snes 4bpp: planes 0 and 1 interleaved per row, then planes 2 and 3
+        for (y, row) in out.iter_mut().enumerate() {
+            let planes = [
+                tile[y * 2],
+                tile[y * 2 + 1],
+                tile[16 + y * 2],
+                tile[16 + y * 2 + 1],
+            ];
+            for (x, px) in row.iter_mut().enumerate() {
+                let bit = 7 - x;
+                *px = planes
+                    .iter()
+                    .enumerate()
+                    .map(|(p, plane)| ((plane >> bit) & 1) << p)
+                    .sum();
+            }
+        }
+        out
+    }
+
+    /// the 16 colours for `mail`, with index 0 transparent. falls back to the first palette if
+    /// the file doesn't have that mail
+    pub fn palette(&self, mail: Mail, gloves: Option<Gloves>) -> [Rgba<u8>; 16] {
+        let colours = self.palettes.get(mail.index()).unwrap_or(&self.palettes[0]);
+        let mut out = [Rgba([0, 0, 0, 0]); 16];
+        out[1..].copy_from_slice(colours);
+        if let (Some(g), Some(glove_colours)) = (gloves, self.gloves) {
+            out[GLOVE_INDEX] = match g {
+                Gloves::PowerGlove => glove_colours[0],
+                Gloves::TitansMitt => glove_colours[1],
+            };
+        }
+        out
+    }
+
+    /// the whole 128x448 sheet in one palette
+    pub fn sheet(&self, palette: &[Rgba<u8>; 16]) -> RgbaImage {
+        let mut out = RgbaImage::new(SHEET_WIDTH, SHEET_HEIGHT);
+        for n in 0..self.tile_count() {
+            let (tx, ty) = (
+                (n % TILES_PER_ROW) as u32 * 8,
+                (n / TILES_PER_ROW) as u32 * 8,
+            );
+            if ty >= SHEET_HEIGHT {
+                break;
+            }
+            for (y, row) in self.tile(n).iter().enumerate() {
+                for (x, index) in row.iter().enumerate() {
+                    out.put_pixel(tx + x as u32, ty + y as u32, palette[*index as usize]);
+                }
+            }
+        }
+        out
+    }
+
+    /// draws one frame of a pose. the image is just big enough to fit every tile
+    pub fn render(&self, frame: &PoseFrame, palette: &[Rgba<u8>; 16]) -> anyhow::Result<RgbaImage> {
+        let sheet = self.sheet(palette);
+        frame.render(&sheet)
+    }
+
+    /// draws every frame of an animation the same size, each with how many game frames it's
+    /// held for
+    pub fn render_animation(
+        &self,
+        frames: &[PoseFrame],
+        palette: &[Rgba<u8>; 16],
+    ) -> anyhow::Result<Vec<(RgbaImage, u32)>> {
+        let bounds = animation_bounds(frames)?.ok_or_else(|| anyhow!("Animation has no tiles"))?;
+        let sheet = self.sheet(palette);
+        frames
+            .iter()
+            .map(|f| Ok((f.render_in(&sheet, bounds)?, f.frames.max(1))))
+            .collect()
+    }
+}
+
+/// the top left of a named 16x16 cell, e.g. `A0` or `AB7`
+pub fn cell_position(name: &str) -> anyhow::Result<(u32, u32)> {
+    let split = name
+        .find(|c: char| c.is_ascii_digit())
+        .ok_or_else(|| anyhow!("Sprite cell {name} has no column"))?;
+    let (letters, column) = name.split_at(split);
+    if letters.is_empty() || !letters.chars().all(|c| c.is_ascii_uppercase()) {
+        return Err(anyhow!("Sprite cell {name} should start with a row letter"));
+    }
+    let off_sheet = || anyhow!("Sprite cell {name} is off the sheet");
+    // A..Z then AA, AB like spreadsheet columns
+    let row = letters
+        .bytes()
+        .try_fold(0u32, |acc, b| {
+            acc.checked_mul(26)?.checked_add((b - b'A') as u32 + 1)
+        })
+        .ok_or_else(off_sheet)?
+        - 1;
+    let column: u32 = column.parse()?;
+    let (x, y) = column
+        .checked_mul(16)
+        .zip(row.checked_mul(16))
+        .ok_or_else(off_sheet)?;
+    if x >= SHEET_WIDTH || y >= SHEET_HEIGHT {
+        return Err(off_sheet());
+    }
+    Ok((x, y))
+}
+
+/// one piece of a pose, cut out of a sheet cell
+#[derive(Debug, Clone, Deserialize)]
+pub struct PoseTile {
+    /// cell name, e.g. `A0`
+    pub image: String,
+    /// where it goes in the pose, relative to the others
+    pub pos: [i32; 2],
+    /// how much of the cell to use. 8x8 pieces are common
+    #[serde(default = "full_cell")]
+    pub dims: [u32; 2],
+    /// where in the cell to start cutting from
+    #[serde(default)]
+    pub from: [u32; 2],
+    /// `h`, `v` or `hv`
+    #[serde(default)]
+    pub flip: Option<String>,
+}
+
+fn full_cell() -> [u32; 2] {
+    [16, 16]
+}
+
+fn one_frame() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct PoseFrame {
+    /// how many game frames this is held for
+    #[serde(default = "one_frame")]
+    pub frames: u32,
+    /// drawn in order, so later tiles go on top
+    pub tiles: Vec<PoseTile>,
+}
+
+/// a box in pose coordinates, as (min x, min y, max x, max y)
+pub type Bounds = (i32, i32, i32, i32);
+
+/// the smallest box that fits every tile of every frame, so a whole animation can be drawn
+/// without it jumping around. `None` if there are no tiles at all
+pub fn animation_bounds(frames: &[PoseFrame]) -> anyhow::Result<Option<Bounds>> {
+    let mut all = vec![];
+    for f in frames {
+        all.extend(f.bounds()?);
+    }
+    Ok(all
+        .into_iter()
+        .reduce(|a, b| (a.0.min(b.0), a.1.min(b.1), a.2.max(b.2), a.3.max(b.3))))
+}
+
+impl PoseTile {
+    /// the bottom right corner in pose coordinates, or an error if a hand-written pose table
+    /// put it somewhere that doesn't fit in an i32
+    pub fn far_corner(&self) -> anyhow::Result<(i32, i32)> {
+        let add = |pos: i32, dim: u32| i32::try_from(dim).ok().and_then(|d| pos.checked_add(d));
+        add(self.pos[0], self.dims[0])
+            .zip(add(self.pos[1], self.dims[1]))
+            .ok_or_else(|| anyhow!("Pose tile {} is too far out", self.image))
+    }
+}
+
+impl PoseFrame {
+    /// `None` if there are no tiles
+    pub fn bounds(&self) -> anyhow::Result<Option<Bounds>> {
+        let mut bounds: Option<Bounds> = None;
+        for t in &self.tiles {
+            let (far_x, far_y) = t.far_corner()?;
+            let tile = (t.pos[0], t.pos[1], far_x, far_y);
+            bounds = Some(match bounds {
+                Some(b) => (
+                    b.0.min(tile.0),
+                    b.1.min(tile.1),
+                    b.2.max(tile.2),
+                    b.3.max(tile.3),
+                ),
+                None => tile,
+            });
+        }
+        Ok(bounds)
+    }
+
+    pub fn render(&self, sheet: &RgbaImage) -> anyhow::Result<RgbaImage> {
+        let bounds = self
+            .bounds()?
+            .ok_or_else(|| anyhow!("Pose frame has no tiles"))?;
+        self.render_in(sheet, bounds)
+    }
+
+    /// draws the frame into an image covering `bounds`. tiles outside it are clipped
+    pub fn render_in(&self, sheet: &RgbaImage, bounds: Bounds) -> anyhow::Result<RgbaImage> {
+        let (min_x, min_y, max_x, max_y) = bounds;
+        let size = |min: i32, max: i32| {
+            max.checked_sub(min)
+                .map(|s| s.max(0) as u32)
+                .ok_or_else(|| anyhow!("Pose frame is too big to draw"))
+        };
+        let mut out = RgbaImage::new(size(min_x, max_x)?, size(min_y, max_y)?);
+        for t in &self.tiles {
+            let (cx, cy) = cell_position(&t.image)?;
+            t.far_corner()?;
+            let flip = t.flip.as_deref().unwrap_or("");
+            let (w, h) = (t.dims[0], t.dims[1]);
+            // the whole cut, so nothing inside the loops can overflow
+            let (start_x, start_y) = cx
+                .checked_add(t.from[0])
+                .zip(cy.checked_add(t.from[1]))
+                .filter(|(x, y)| x.checked_add(w).is_some() && y.checked_add(h).is_some())
+                .ok_or_else(|| anyhow!("Pose tile {} reaches off the sheet", t.image))?;
+            for y in 0..h {
+                for x in 0..w {
+                    let sx = start_x + if flip.contains('h') { w - 1 - x } else { x };
+                    let sy = start_y + if flip.contains('v') { h - 1 - y } else { y };
+                    if sx >= sheet.width() || sy >= sheet.height() {
+                        continue;
+                    }
+                    let px = *sheet.get_pixel(sx, sy);
+                    if px.0[3] == 0 {
+                        continue;
+                    }
+                    let ox = t.pos[0] as i64 - min_x as i64 + x as i64;
+                    let oy = t.pos[1] as i64 - min_y as i64 + y as i64;
+                    if ox < 0 || oy < 0 || ox >= out.width() as i64 || oy >= out.height() as i64 {
+                        continue;
+                    }
+                    out.put_pixel(ox as u32, oy as u32, px);
+                }
+            }
+        }
+        Ok(out)
+    }
+}
+
+/// animation name -> direction -> frames, e.g. `poses["Walk"]["down"]`. the sheet layout is the
+/// same for every sprite, so one table works for all of them
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct PoseTable(pub HashMap<String, HashMap<String, Vec<PoseFrame>>>);
+
+impl PoseTable {
+    /// `assets/poses.json`: standing, the walk cycle and a sword swing in all four directions,
+    /// and holding an item up
+    pub fn standard() -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(include_str!("../assets/poses.json"))?)
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> anyhow::Result<Self> {
+        Ok(serde_json::from_str(&std::fs::read_to_string(path)?)?)
+    }
+
+    pub fn frames(&self, animation: &str, direction: &str) -> anyhow::Result<&[PoseFrame]> {
+        self.0
+            .get(animation)
+            .ok_or_else(|| anyhow!("No animation called {animation}"))?
+            .get(direction)
+            .map(|f| f.as_slice())
+            .ok_or_else(|| anyhow!("{animation} has no {direction} direction"))
+    }
+
+    /// the directions `animation` has, down, up, left and right first
+    pub fn directions(&self, animation: &str) -> Vec<&str> {
+        let order = |d: &str| {
+            ["down", "up", "left", "right"]
+                .iter()
+                .position(|o| *o == d)
+                .unwrap_or(usize::MAX)
+        };
+        let mut directions: Vec<&str> = self
+            .0
+            .get(animation)
+            .map(|d| d.keys().map(|k| k.as_str()).collect())
+            .unwrap_or_default();
+        directions.sort_by_key(|d| (order(d), d.to_string()));
+        directions
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cut_off_headers_are_errors() {
+        for len in 0..0x20 {
+            let mut data = b"ZSPR".to_vec();
+            data.resize(len.max(4), 0);
+            assert!(Zspr::parse(&data).is_err());
+        }
+    }
+
+    #[test]
+    fn standard_poses_are_all_on_the_sheet() {
+        let poses = PoseTable::standard().unwrap();
+        for animation in ["Stand", "Walk", "Sword"] {
+            assert_eq!(poses.directions(animation), ["down", "up", "left", "right"]);
+        }
+        assert_eq!(poses.directions("Item"), ["down"]);
+        for frames in poses.0.values().flat_map(|d| d.values()) {
+            assert_eq!(
+                animation_bounds(frames).unwrap().map(|b| b.3 - b.1 >= 24),
+                Some(true)
+            );
+            for tile in frames.iter().flat_map(|f| &f.tiles) {
+                cell_position(&tile.image).unwrap();
+            }
+        }
+    }
+
+    /// a ZSPR with one tile and every palette, with `gloves` or without like older files
+    fn tiny_zspr(gloves: bool) -> Vec<u8> {
+        let mut tile = [0u8; TILE_BYTES];
+        // row 0: planes 0 to 3 each set one pixel, so x = 0..4 are indices 1, 2, 4 and 8
+        tile[0] = 0b1000_0000;
+        tile[1] = 0b0100_0000;
+        tile[16] = 0b0010_0000;
+        tile[17] = 0b0001_0000;
+        // row 1: every plane on the last pixel, so index 15
+        tile[2] = 1;
+        tile[3] = 1;
+        tile[18] = 1;
+        tile[19] = 1;
+        let mut colours: Vec<u16> = (0..Mail::ALL.len() * COLOURS_PER_MAIL)
+            .map(|n| n as u16)
+            .collect();
+        colours[0] = 0x001f; // green mail, index 1: pure red
+        colours[COLOURS_PER_MAIL] = 0x03e0; // blue mail, index 1: pure green
+        if gloves {
+            colours.extend([0x7c00, 0x0210]);
+        }
+        let palette: Vec<u8> = colours.iter().flat_map(|c| c.to_le_bytes()).collect();
+
+        let mut strings = vec![];
+        for s in ["Tiny", "Me"] {
+            strings.extend(s.encode_utf16().chain([0]).flat_map(|u| u.to_le_bytes()));
+        }
+        strings.extend(b"ME\0");
+        let sprite_offset = 0x1D + strings.len();
+        let palette_offset = sprite_offset + tile.len();
+
+        let mut data = b"ZSPR".to_vec();
+        data.push(1);
+        data.extend(0xdeadbeefu32.to_le_bytes());
+        data.extend((sprite_offset as u32).to_le_bytes());
+        data.extend((tile.len() as u16).to_le_bytes());
+        data.extend((palette_offset as u32).to_le_bytes());
+        data.extend((palette.len() as u16).to_le_bytes());
+        data.extend(1u16.to_le_bytes());
+        data.extend([0; 6]);
+        data.extend(strings);
+        data.extend(tile);
+        data.extend(palette);
+        data
+    }
+
+    #[test]
+    fn decodes_a_whole_zspr() {
+        let zspr = Zspr::parse(&tiny_zspr(true)).unwrap();
+        assert_eq!(
+            (zspr.version, zspr.checksum, zspr.sprite_type),
+            (1, 0xdeadbeef, 1)
+        );
+        assert_eq!(
+            (
+                zspr.display_name.as_str(),
+                zspr.author.as_str(),
+                zspr.author_rom.as_str()
+            ),
+            ("Tiny", "Me", "ME")
+        );
+        assert_eq!(zspr.tile_count(), 1);
+        assert_eq!(zspr.tile(0)[0], [1, 2, 4, 8, 0, 0, 0, 0]);
+        assert_eq!(zspr.tile(0)[1], [0, 0, 0, 0, 0, 0, 0, 15]);
+        assert_eq!(zspr.tile(0)[2], [0; 8]);
+        // past the end is blank rather than a panic
+        assert_eq!(zspr.tile(1), [[0; 8]; 8]);
+
+        let red = Rgba([255, 0, 0, 255]);
+        let green = Rgba([0, 255, 0, 255]);
+        let blue = Rgba([0, 0, 255, 255]);
+        assert_eq!(zspr.palettes.len(), 4);
+        let palette = zspr.palette(Mail::Green, None);
+        assert_eq!(palette[0].0[3], 0);
+        assert_eq!(palette[1], red);
+        assert_eq!(zspr.palette(Mail::Blue, None)[1], green);
+        assert_eq!(zspr.gloves, Some([blue, Rgba([132, 132, 0, 255])]));
+        assert_eq!(
+            zspr.palette(Mail::Green, Some(Gloves::PowerGlove))[GLOVE_INDEX],
+            blue
+        );
+
+        let sheet = zspr.sheet(&palette);
+        assert_eq!(sheet.dimensions(), (SHEET_WIDTH, SHEET_HEIGHT));
+        assert_eq!(*sheet.get_pixel(0, 0), red);
+        assert_eq!(*sheet.get_pixel(1, 0), palette[2]);
+        assert_eq!(*sheet.get_pixel(7, 1), palette[15]);
+        assert_eq!(sheet.get_pixel(4, 0).0[3], 0);
+    }
+
+    #[test]
+    fn older_zsprs_have_no_gloves() {
+        let zspr = Zspr::parse(&tiny_zspr(false)).unwrap();
+        assert_eq!(zspr.gloves, None);
+        // asking for gloves anyway leaves the mail colour alone
+        assert_eq!(
+            zspr.palette(Mail::Red, Some(Gloves::TitansMitt)),
+            zspr.palette(Mail::Red, None)
+        );
+    }
+
+    #[test]
+    fn bgr555_spreads_to_the_full_range() {
+        assert_eq!(bgr555(0), Rgba([0, 0, 0, 255]));
+        assert_eq!(bgr555(0x7fff), Rgba([255, 255, 255, 255]));
+        assert_eq!(bgr555(0x001f), Rgba([255, 0, 0, 255]));
+        assert_eq!(bgr555(0x7c00), Rgba([0, 0, 255, 255]));
+        // the top bit isn't a colour
+        assert_eq!(bgr555(0x8000), bgr555(0));
+    }
+
+    #[test]
+    fn poses_too_far_out_are_errors() {
+        let sheet = RgbaImage::new(SHEET_WIDTH, SHEET_HEIGHT);
+        let frame = |pos: [i32; 2], dims: [u32; 2], from: [u32; 2]| PoseFrame {
+            frames: 1,
+            tiles: vec![PoseTile {
+                image: "A0".to_string(),
+                pos,
+                dims,
+                from,
+                flip: Some("hv".to_string()),
+            }],
+        };
+        assert!(frame([0, 0], [16, 16], [0, 0]).render(&sheet).is_ok());
+        assert!(frame([i32::MAX, 0], [16, 16], [0, 0])
+            .render(&sheet)
+            .is_err());
+        assert!(frame([0, 0], [u32::MAX, 1], [0, 0]).render(&sheet).is_err());
+        assert!(frame([0, 0], [16, 16], [u32::MAX, 0])
+            .render(&sheet)
+            .is_err());
+        assert!(frame([0, 0], [16, 16], [0, 0])
+            .render_in(&sheet, (i32::MIN, 0, i32::MAX, 16))
+            .is_err());
+    }
+
+    #[test]
+    fn cell_names() {
+        assert_eq!(cell_position("A0").unwrap(), (0, 0));
+        assert_eq!(cell_position("B3").unwrap(), (48, 16));
+        assert_eq!(cell_position("AB7").unwrap(), (112, 432));
+        assert!(cell_position("AC0").is_err());
+        assert!(cell_position("A8").is_err());
+        assert!(cell_position("ZZZZZZZZZZZZ0").is_err());
+        assert!(cell_position("A99999999999").is_err());
+        assert!(cell_position("A4294967295").is_err());
+    }
+}
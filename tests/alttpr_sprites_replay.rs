@@ -0,0 +1,45 @@
+mod common;
+
+use common::{replay, scratch_dir, stdout};
+
+const EXE: &str = env!("CARGO_BIN_EXE_alttpr_sprites");
+
+#[test]
+fn draws_cards_and_a_gallery_from_recorded_responses() {
+    let dir = scratch_dir("alttpr_sprites_cards");
+    let output = replay(EXE, &dir, &["--all", "--gallery"]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("2 of 2 sprites selected"), "{}", out);
+    assert!(out.contains("Downloads: 2 fetched"), "{}", out);
+    for card in ["001.link.1.zspr_big.png", "hat_kid.2.zspr_big.png"] {
+        let card = image::open(dir.join("sprites/big").join(card)).unwrap();
+        assert_eq!((card.width(), card.height()), (384, 384));
+    }
+    assert!(dir.join("sprites/gallery/gallery.png").exists());
+    let index = std::fs::read_to_string(dir.join("sprites/gallery/index.html")).unwrap();
+    assert!(index.contains("../big/hat_kid.2.zspr_big.png"));
+}
+
+#[test]
+fn second_run_needs_nothing_but_the_cache() {
+    let dir = scratch_dir("alttpr_sprites_offline");
+    assert!(replay(EXE, &dir, &["--name", "hat kid"]).status.success());
+    // with every response gone, only what the first run cached can be used
+    std::fs::remove_dir_all(dir.join("sprites/big")).unwrap();
+    let output = replay(EXE, &dir, &["--offline", "--name", "hat kid"]);
+    assert!(output.status.success());
+    assert!(dir.join("sprites/big/hat_kid.2.zspr_big.png").exists());
+}
+
+// `--usage` only comes with the full metadata
+#[cfg(feature = "rich_sprites")]
+#[test]
+fn filters_pick_from_the_recorded_list() {
+    let dir = scratch_dir("alttpr_sprites_filter");
+    let output = replay(EXE, &dir, &["--dry-run", "--usage", "smz3"]);
+    assert!(output.status.success());
+    let out = stdout(&output);
+    assert!(out.contains("1 of 2 sprites selected"), "{}", out);
+    assert!(out.lines().any(|l| l == "Link"));
+}
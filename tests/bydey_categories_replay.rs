@@ -0,0 +1,14 @@
+mod common;
+
+use common::{replay, scratch_dir, stdout};
+
+#[test]
+fn draws_a_card_per_personal_best_from_recorded_responses() {
+    let dir = scratch_dir("bydey_categories");
+    let output = replay(env!("CARGO_BIN_EXE_bydey_categories"), &dir, &[]);
+    assert!(output.status.success(), "{}", stdout(&output));
+    // the subcategory's label comes from the recorded /variables response
+    let card = dir.join("bydey_categories/pbs/a_link_to_the_past_no_major_glitches_jp_1.0.png");
+    let card = image::open(card).unwrap();
+    assert_eq!((card.width(), card.height()), (512, 512));
+}
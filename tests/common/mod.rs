@@ -0,0 +1,35 @@
+//! running the binaries against the responses in `tests/fixtures`
+
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// an empty directory for a binary to run in, so what it writes doesn't land in the repo
+pub fn scratch_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("image_misc_{name}_{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// runs `exe` in `dir` with every request answered from the fixtures
+pub fn replay(exe: &str, dir: &Path, args: &[&str]) -> Output {
+    let output = Command::new(exe)
+        .args(args)
+        .current_dir(dir)
+        .env("FETCH_MODE", "replay")
+        .env(
+            "FETCH_FIXTURES",
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures"),
+        )
+        .env_remove("ASSET_CACHE_DIR")
+        .env_remove("ASSET_CACHE_MAX_MB")
+        .output()
+        .unwrap();
+    println!("{}", String::from_utf8_lossy(&output.stdout));
+    println!("{}", String::from_utf8_lossy(&output.stderr));
+    output
+}
+
+pub fn stdout(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stdout).into_owned()
+}